@@ -1,119 +1,686 @@
+use crate::config::{Config, FilenameRules, State, ART_FILTERS, SPLIT_RANGE};
 use crate::functions::*;
 
+use arboard::Clipboard;
+use chrono::Local;
 use id3::{Tag, TagLike};
-use ratatui_image::{picker::Picker, protocol::StatefulProtocol};
+use image::{DynamicImage, ImageReader, Limits};
+use ratatui_image::{errors::Errors, picker::Picker, protocol::StatefulProtocol, FilterType};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     error::Error,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
 pub struct App {
+    /// What is being edited, for the title bar: the directory or the list of roots.
+    location: String,
     files: Vec<String>,
     selected_file: usize,
+    /// Files marked for batch operations; when empty they apply to every file.
+    selected_files: HashSet<String>,
     fields: Vec<String>,
     selected_field: usize,
+    advanced_fields: bool,
     input_buffer: String,
     current_field: Option<String>,
+    /// Set while editing straight from the files list, which is where the edit returns to.
+    quick_edit: bool,
+    /// The last value saved for each field this session, offered again when editing.
+    last_values: HashMap<String, String>,
     current_file: String,
     mode: Mode,
     message: String,
-    pub album_art_cache: HashMap<String, Arc<Mutex<StatefulProtocol>>>,
-    pub image_picker: Picker,
+    prompt: Option<PromptKind>,
+    completions: Vec<String>,
+    completion_index: usize,
+    replace_draft: Option<FindReplace>,
+    pending: Option<PendingAction>,
+    /// Progress of a running ReplayGain analysis.
+    #[cfg(feature = "replaygain")]
+    replaygain: Option<std::sync::mpsc::Receiver<crate::replaygain::Progress>>,
+    /// Loudness levels of each file whose waveform was drawn, `None` when it could not
+    /// be decoded.
+    #[cfg(feature = "waveform")]
+    waveforms: HashMap<String, Option<Vec<f32>>>,
+    /// The file whose waveform is being computed.
+    #[cfg(feature = "waveform")]
+    waveform_job: Option<(String, std::sync::mpsc::Receiver<crate::waveform::Levels>)>,
+    confirm_writes: bool,
+    confirm_scroll: usize,
+    /// The configured extensions, to tell probed files apart from the rest.
+    extensions: Vec<String>,
+    preview_fields: Vec<(String, String)>,
+    file_info: HashMap<String, FileInfo>,
+    /// Parsed tags per file; `None` marks a file whose tag could not be read.
+    tag_cache: HashMap<String, Option<TagInfo>>,
+    /// The modification time of each file when the app last read or wrote its tag, to
+    /// notice other programs writing to it in between.
+    read_mtimes: HashMap<String, SystemTime>,
+    /// The tags of each file as they were when first read, to show what changed since.
+    original_tags: HashMap<String, TagInfo>,
+    /// The id3 error of each file whose tag could not be read, for diagnosis.
+    read_errors: HashMap<String, String>,
+    clipboard: Option<Clipboard>,
+    log: Vec<String>,
+    log_scroll: usize,
+    /// Every change written to each file this session, oldest first.
+    history: HashMap<String, Vec<ChangeRecord>>,
+    history_scroll: usize,
+    table: Option<TableEdit>,
+    form: Option<EditForm>,
+    /// Draw without colours, as asked for by `NO_COLOR` or toggled at runtime.
+    monochrome: bool,
+    /// Drawn in the art panel for files without a cover.
+    art_placeholder: Vec<String>,
+    /// Position in `ART_FILTERS` of the filter album art is resampled with.
+    art_filter: usize,
+    filename_rules: FilenameRules,
+    /// The configured transforms, offered by name.
+    transforms: Vec<TransformRule>,
+    stats: Option<LibraryStats>,
+    /// The playing time of the list being measured for the open stats.
+    stats_job: Option<std::sync::mpsc::Receiver<(Duration, usize)>>,
+    /// The issues found by the last library check, while its report is open.
+    issues: Option<Vec<Issue>>,
+    issues_scroll: usize,
+    buffer_writes: bool,
+    autosave_interval: Option<Duration>,
+    autosave_edits: Option<usize>,
+    /// When the oldest unsaved edit was made, for the autosave timer.
+    dirty_since: Option<Instant>,
+    /// Unsaved edits, kept in file order.
+    buffered: Vec<BufferedEdit>,
+    review_position: usize,
+    /// Set by a first quit with unsaved edits, so a second one goes through.
+    quit_requested: bool,
+    /// The value the field had when editing started, to tell whether Esc loses anything.
+    editing_original: String,
+    /// Set by a first Esc over a changed value, so a second one discards it.
+    discard_requested: bool,
+    /// Cover taken with `yank_art`, as picture data and MIME type.
+    yanked_art: Option<(Vec<u8>, String)>,
+    /// What `undo` can reverse, most recent last.
+    undo_stack: Vec<Undo>,
+    /// Lines visible in the last drawn scrollable view, the step for PageUp/PageDown.
+    view_height: usize,
+    show_art: bool,
+    path_display: PathDisplay,
+    /// Whether the files are listed by album and track, under a heading per album.
+    sort_by_album: bool,
+    files_width: u16,
+    preview_width: u16,
+    quick_track: bool,
+    pub album_art_cache: HashMap<ArtKey, AlbumArt>,
+    /// Cached covers from least to most recently used, for dropping the oldest.
+    art_cache_order: VecDeque<ArtKey>,
+    /// Covers per row of the gallery as configured, `None` to fit the width.
+    gallery_columns: Option<u16>,
+    /// Covers per row as the gallery was last drawn, the step for Up and Down.
+    gallery_width: usize,
+    /// Covers on screen as the gallery was last drawn.
+    gallery_cells: usize,
+    gallery_selected: usize,
+    /// `None` when the terminal cannot be set up for images, leaving art disabled.
+    pub image_picker: Option<Picker>,
+}
+
+/// Embedded art is cached per track, sidecar art per image file so an album shares one decode.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum ArtKey {
+    Embedded(String),
+    Sidecar(PathBuf),
 }
 
 #[derive(Clone)]
-pub struct TagInfo {
-    pub title: String,
-    pub artist: String,
+pub struct AlbumArt {
+    /// `None` for a picture too large to decode, which still has its size to show.
+    pub protocol: Option<Arc<Mutex<StatefulProtocol>>>,
+    pub width: u32,
+    pub height: u32,
+    /// Image format for display, such as `JPEG`.
+    pub format: String,
+    pub sidecar: Option<PathBuf>,
+}
+
+/// A typed edit held back from disk while `buffer_writes` is on.
+pub struct BufferedEdit {
+    pub file: String,
+    pub field: String,
+    /// The value on disk when the field was first edited.
+    pub old: String,
+    pub new: String,
+    /// Cleared in the review to leave the edit out of the next save.
+    pub enabled: bool,
+}
+
+/// A file of the other copy paired with a listed file of the same track number.
+pub struct TrackMatch {
+    pub source: String,
+    pub target: String,
+    /// The (field, old, new) edits to make to the target.
+    pub edits: Vec<(String, String, String)>,
+}
+
+/// How the files of a batch write went, for the message that closes it.
+#[derive(Default)]
+struct BatchOutcome {
+    done: usize,
+    /// Files another program changed after they were read, left alone.
+    changed_on_disk: Vec<String>,
+    failed: usize,
+    /// The first error met, shown in the message; all of them go to the log.
+    first_error: Option<String>,
+}
+
+impl BatchOutcome {
+    fn fail(&mut self, error: String) {
+        self.failed += 1;
+        self.first_error.get_or_insert(error);
+    }
+
+    /// `summary` with a ✓, or with a ✗ and what was skipped or failed.
+    fn message(&self, summary: String) -> String {
+        let mut problems = Vec::new();
+        match self.changed_on_disk.as_slice() {
+            [] => {}
+            [file] => problems.push(format!("skipped {} as it changed on disk", file)),
+            files => problems.push(format!(
+                "skipped {} that changed on disk (see the log)",
+                files.len()
+            )),
+        }
+        if let Some(error) = &self.first_error {
+            problems.push(format!("{} failed: {}", self.failed, error));
+        }
+        if problems.is_empty() {
+            format!("✓ {}", summary)
+        } else {
+            format!("✗ {}, {}", summary, problems.join(", "))
+        }
+    }
+}
+
+/// One file of the table edit, with a value per `TABLE_COLUMNS` entry.
+pub struct TableRow {
+    pub file: String,
+    /// The values as they were when the table opened or the row was last saved.
+    pub saved: Vec<String>,
+    pub values: Vec<String>,
+}
+
+impl TableRow {
+    pub fn is_dirty(&self) -> bool {
+        self.saved != self.values
+    }
+}
+
+/// The spreadsheet-like editor over several files, see `Mode::TableEdit`.
+pub struct TableEdit {
+    pub rows: Vec<TableRow>,
+    pub row: usize,
+    pub column: usize,
+}
+
+/// Every field of one file as inputs at once, see `Mode::Form`.
+pub struct EditForm {
+    pub file: String,
+    pub fields: Vec<String>,
+    /// The values as they were when the form opened or was last saved.
+    pub saved: Vec<String>,
+    pub values: Vec<String>,
+    pub focus: usize,
+}
+
+impl EditForm {
+    pub fn is_changed(&self, index: usize) -> bool {
+        self.saved[index] != self.values[index]
+    }
+}
+
+/// One change written to a file this session, for its history.
+pub struct ChangeRecord {
+    pub time: String,
+    pub change: String,
+}
+
+/// An operation `undo` can reverse.
+pub enum Undo {
+    /// Files renamed together, as (old path, new path).
+    Rename(Vec<(String, String)>),
+}
+
+/// Filesystem metadata for a listed file, refreshed whenever the app writes to it.
+#[derive(Clone, Default)]
+pub struct FileInfo {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileInfo {
+    fn read(filename: &str) -> Self {
+        match fs::metadata(filename) {
+            Ok(metadata) => FileInfo {
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            },
+            Err(_) => FileInfo::default(),
+        }
+    }
+}
+
+/// Where a file sits within its album, worked out from the other files of its folder.
+pub struct AlbumPosition {
+    pub track: u32,
+    /// The track total of the tag, or else the highest track number found, at most
+    /// `MAX_ALBUM_TRACKS`.
+    pub count: u32,
     pub album: String,
-    pub year: String,
-    pub track: String,
+    /// The track numbers the files of the album have.
+    tracks: HashSet<u32>,
 }
 
+impl AlbumPosition {
+    /// Track numbers up to `count` that no file of the album has.
+    pub fn missing(&self) -> impl Iterator<Item = u32> + '_ {
+        (1..=self.count).filter(|track| !self.tracks.contains(track))
+    }
+
+    pub fn missing_count(&self) -> u32 {
+        let present = self
+            .tracks
+            .iter()
+            .filter(|track| (1..=self.count).contains(*track))
+            .count();
+        self.count - present as u32
+    }
+}
+
+/// Highest track total believed when working out an album position. A bogus total
+/// such as 1/4294967295 would otherwise list billions of missing tracks.
+const MAX_ALBUM_TRACKS: u32 = 999;
+
+/// Name template offered by the normalize macro.
+const NORMALIZE_TEMPLATE: &str = "{track} - {artist} - {title}";
+
 #[derive(PartialEq)]
 pub enum Mode {
     FileSelection,
     FieldSelection,
     Editing,
+    Prompt,
+    Confirm,
+    Log,
+    History,
+    /// A row per file and a column per field, typed into directly.
+    TableEdit,
+    Form,
+    FrameInfo,
+    Stats,
+    /// The report of a library check.
+    Verify,
+    /// A grid of the covers of the listed files.
+    Gallery,
+    Review,
+}
+
+/// A movement within one of the scrollable views.
+#[derive(Clone, Copy)]
+pub enum Scroll {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+}
+
+impl Scroll {
+    /// The position after this movement in a view of `len` lines showing `page` at once.
+    pub fn apply(self, position: usize, len: usize, page: usize) -> usize {
+        let last = len.saturating_sub(1);
+        let page = page.max(1);
+        match self {
+            Scroll::Up => position.saturating_sub(1),
+            Scroll::Down => (position + 1).min(last),
+            Scroll::PageUp => position.saturating_sub(page),
+            Scroll::PageDown => (position + page).min(last),
+            Scroll::Home => 0,
+            Scroll::End => last,
+        }
+    }
+}
+
+/// How entries of the files list are labelled. Files are always keyed by their full path.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PathDisplay {
+    Full,
+    Basename,
+}
+
+/// What the text typed in `Mode::Prompt` is for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    ArtPath,
+    ReplaceField,
+    ReplaceSearch,
+    ReplaceWith,
+    Rename,
+    RenameTemplate,
+    Normalize,
+    AlbumArtDir,
+    CopyByTrack,
+    ExportJson,
+    ImportJson,
+    Transform,
+}
+
+impl PromptKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PromptKind::ArtPath => "Path of the image to embed",
+            PromptKind::ReplaceField => "Field to search in",
+            PromptKind::ReplaceSearch => "Text to find",
+            PromptKind::ReplaceWith => "Replace with",
+            PromptKind::Rename => "New file name",
+            PromptKind::Normalize => {
+                "Fill tags from the file names, then rename to ({track} {artist} {title} {album} {date})"
+            }
+            PromptKind::RenameTemplate => {
+                "Rename to template ({track} {artist} {title} {album} {date})"
+            }
+            PromptKind::AlbumArtDir => "Folder of covers named by album",
+            PromptKind::CopyByTrack => "Folder of the copy to tag, matched by track number",
+            PromptKind::ExportJson => "File to export the tags to, as JSON",
+            PromptKind::ImportJson => "JSON export to import the tags from",
+            PromptKind::Transform => "Transform to apply",
+        }
+    }
+}
+
+/// An operation touching several files, held in `Mode::Confirm` until the user accepts it.
+pub enum PendingAction {
+    Replace {
+        find: FindReplace,
+        /// Files that would change, with their new value.
+        affected: Vec<(String, String)>,
+    },
+    SwapArtistTitle {
+        file: String,
+        artist: String,
+        title: String,
+    },
+    RenameAll {
+        /// Old and new path of each file, with the reason it cannot be renamed if any.
+        renames: Vec<(String, String, Option<String>)>,
+        /// Tag edits made before renaming, as (field, old, new) by file. Empty for a
+        /// plain rename.
+        tag_changes: HashMap<String, Vec<(String, String, String)>>,
+    },
+    /// Copies tags from the listed files to another copy of the album, matching files by
+    /// track number.
+    CopyByTrack {
+        target_dir: String,
+        matches: Vec<TrackMatch>,
+        /// Target files left alone, with the reason.
+        unmatched: Vec<(String, String)>,
+    },
+    /// Applies the tags of a JSON export to the files it names.
+    ImportJson { source: String, plan: ImportPlan },
+    /// Sets the track total of every file in a folder to the number of files in it.
+    TrackTotals {
+        folder: String,
+        /// Also number the files 1 to N in list order.
+        renumber: bool,
+        /// Each file with its current track and the new one, `None` when it has no
+        /// number to add the total to.
+        changes: Vec<(String, String, Option<String>)>,
+    },
+    /// A field edit held back because another program changed the file meanwhile.
+    /// Always asked about; declining reloads the file instead.
+    Overwrite {
+        file: String,
+        field: String,
+        value: String,
+    },
+    Transform {
+        name: String,
+        field: String,
+        /// Each file the transform changes, with its value before and after.
+        changes: Vec<(String, String, String)>,
+    },
+    /// Fields the tracks of an album folder disagree on, each to be set to its most
+    /// common value.
+    UnifyAlbums { conflicts: Vec<AlbumConflict> },
+    /// Folders that look like compilations, to tag with "Various Artists".
+    MarkCompilations { compilations: Vec<Compilation> },
+    /// Wipes every tag from one file; never applied without asking.
+    StripTags { file: String },
+    RemoveArt {
+        /// Files with embedded pictures, with the size of their picture data.
+        affected: Vec<(String, u64)>,
+    },
 }
 
 impl App {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        let files = get_mp3_files(".")?;
+    pub fn new(roots: &[String], probe: bool) -> Result<Self, Box<dyn Error>> {
+        let config = Config::load()?;
+        let location = if roots.is_empty() {
+            std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_else(|_| ".".to_string())
+        } else {
+            roots.join(", ")
+        };
+        let Scan { files, warnings } = scan_roots(roots, &config.extensions, probe);
+        let art_placeholder = config.art_placeholder()?;
+        let transforms = config
+            .transforms
+            .iter()
+            .map(TransformRule::compile)
+            .collect::<Result<Vec<_>, _>>()?;
+        let advanced_fields = State::load().advanced_fields;
         // Initialize the image picker
-        let image_picker = Picker::from_fontsize((10, 24));
+        let art_filter = config.art_filter()?;
+        let mut image_picker = create_picker(&config);
+        if let (Some(picker), Some(protocol)) = (image_picker.as_mut(), config.art_protocol()?) {
+            picker.set_protocol_type(protocol);
+        }
+        let file_info = files
+            .iter()
+            .map(|file| (file.clone(), FileInfo::read(file)))
+            .collect();
 
         Ok(App {
+            location,
             files: files.clone(),
             selected_file: 0,
-            fields: vec![
-                "Song Name".to_string(),
-                "Artist".to_string(),
-                "Album".to_string(),
-                "Date".to_string(),
-                "Track".to_string(),
-            ],
+            selected_files: HashSet::new(),
+            fields: field_names(advanced_fields),
             selected_field: 0,
+            advanced_fields,
             input_buffer: String::new(),
             current_field: None,
+            quick_edit: false,
+            last_values: HashMap::new(),
             current_file: files.first().cloned().unwrap_or_default(),
             mode: Mode::FileSelection,
-            message: String::from("Select a file to edit"),
+            message: if warnings.is_empty() {
+                String::from("Select a file to edit")
+            } else {
+                format!(
+                    "✗ {} entries could not be listed (see the log)",
+                    warnings.len()
+                )
+            },
+            prompt: None,
+            completions: Vec::new(),
+            completion_index: 0,
+            replace_draft: None,
+            pending: None,
+            #[cfg(feature = "replaygain")]
+            replaygain: None,
+            #[cfg(feature = "waveform")]
+            waveforms: HashMap::new(),
+            #[cfg(feature = "waveform")]
+            waveform_job: None,
+            confirm_writes: config.confirm_writes,
+            confirm_scroll: 0,
+            extensions: config.extensions.clone(),
+            preview_fields: config.preview_fields.clone(),
+            file_info,
+            tag_cache: HashMap::new(),
+            read_mtimes: HashMap::new(),
+            original_tags: HashMap::new(),
+            read_errors: HashMap::new(),
+            clipboard: None,
+            log: warnings
+                .iter()
+                .map(|warning| format!("{} skipped {}", Local::now().format("%H:%M"), warning))
+                .collect(),
+            log_scroll: 0,
+            history: HashMap::new(),
+            history_scroll: 0,
+            table: None,
+            form: None,
+            // https://no-color.org: set and not empty
+            monochrome: std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()),
+            art_placeholder,
+            art_filter,
+            filename_rules: config.filenames.clone(),
+            transforms,
+            stats: None,
+            stats_job: None,
+            issues: None,
+            issues_scroll: 0,
+            buffer_writes: config.buffer_writes,
+            autosave_interval: config.autosave_seconds.map(Duration::from_secs),
+            autosave_edits: config.autosave_edits.filter(|&edits| edits > 0),
+            dirty_since: None,
+            buffered: Vec::new(),
+            review_position: 0,
+            quit_requested: false,
+            editing_original: String::new(),
+            discard_requested: false,
+            yanked_art: None,
+            undo_stack: Vec::new(),
+            view_height: 0,
+            show_art: true,
+            path_display: PathDisplay::Full,
+            sort_by_album: false,
+            files_width: config.files_width(),
+            preview_width: config.preview_width(),
+            quick_track: false,
             album_art_cache: HashMap::new(),
+            art_cache_order: VecDeque::new(),
+            gallery_columns: config.gallery_columns.filter(|columns| *columns > 0),
+            gallery_width: 1,
+            gallery_cells: 0,
+            gallery_selected: 0,
             image_picker,
         })
     }
 
-    pub fn load_album_art(&mut self, filename: &str) -> Option<Arc<Mutex<StatefulProtocol>>> {
+    pub fn load_album_art(&mut self, filename: &str) -> Option<AlbumArt> {
+        if !self.art_enabled() {
+            return None;
+        }
+        let embedded_key = ArtKey::Embedded(filename.to_string());
+
         // Check cache first
-        if let Some(cached) = self.album_art_cache.get(filename) {
-            return Some(cached.clone());
+        if let Some(cached) = self.album_art_cache.get(&embedded_key).cloned() {
+            self.touch_art(&embedded_key);
+            return Some(cached);
         }
 
         // Extract album art from MP3 file
-        if let Some(art_data) = self.extract_album_art_bytes(filename) {
-            // Try to decode the image
-            if let Ok(dyn_img) = image::load_from_memory(&art_data) {
-                // Create protocol for rendering
-                let protocol = self.image_picker.new_resize_protocol(dyn_img);
-                let protocol_arc = Arc::new(Mutex::new(protocol));
+        if let Ok(Some((art_data, mime_type))) = self.extract_album_art_bytes(filename) {
+            return self.cache_album_art(embedded_key, &art_data, Some(mime_type), None);
+        }
 
-                self.album_art_cache
-                    .insert(filename.to_string(), protocol_arc.clone());
-                return Some(protocol_arc);
-            }
+        // Fall back to a cover image stored next to the track
+        let sidecar = find_sidecar_art(filename)?;
+        let sidecar_key = ArtKey::Sidecar(sidecar.clone());
+        if let Some(cached) = self.album_art_cache.get(&sidecar_key).cloned() {
+            self.touch_art(&sidecar_key);
+            return Some(cached);
         }
 
-        None
+        let art_data = fs::read(&sidecar).ok()?;
+        self.cache_album_art(sidecar_key, &art_data, None, Some(sidecar))
     }
 
-    fn extract_album_art_bytes(&self, filename: &str) -> Option<Vec<u8>> {
-        match Tag::read_from_path(filename) {
-            Ok(tag) => {
-                if let Some(picture) = tag.pictures().next() {
-                    return Some(picture.data.clone());
-                }
-                None
+    /// Decodes and caches a cover. `mime_type` is what the tag claims the picture is;
+    /// without it the format is sniffed from the data.
+    fn cache_album_art(
+        &mut self,
+        key: ArtKey,
+        art_data: &[u8],
+        mime_type: Option<String>,
+        sidecar: Option<PathBuf>,
+    ) -> Option<AlbumArt> {
+        // Keep the source dimensions so the layout can preserve the aspect ratio
+        let ((width, height), image) = decode_art(art_data)?;
+
+        // Create protocol for rendering
+        let picker = self.image_picker.as_mut()?;
+        let protocol = image.map(|image| Arc::new(Mutex::new(picker.new_resize_protocol(image))));
+        let mime_type = mime_type
+            .or_else(|| {
+                image::guess_format(art_data)
+                    .ok()
+                    .map(|format| format.to_mime_type().to_string())
+            })
+            .unwrap_or_default();
+        let art = AlbumArt {
+            protocol,
+            width,
+            height,
+            format: image_format_name(&mime_type),
+            sidecar,
+        };
+
+        self.album_art_cache.insert(key.clone(), art.clone());
+        self.touch_art(&key);
+        // The gallery goes through many covers, which would otherwise all stay decoded,
+        // but each one on screen has to stay or it is decoded again every frame
+        while self.art_cache_order.len() > ART_CACHE_LIMIT.max(self.gallery_cells) {
+            if let Some(oldest) = self.art_cache_order.pop_front() {
+                self.album_art_cache.remove(&oldest);
             }
-            Err(_) => None,
         }
+        Some(art)
     }
-    pub fn has_album_art(&self, filename: &str) -> bool {
-        self.album_art_cache.contains_key(filename) || {
-            match Tag::read_from_path(filename) {
-                Ok(tag) => tag.pictures().next().is_some(),
-                Err(_) => false,
-            }
+
+    /// Marks a cached cover as the most recently used.
+    fn touch_art(&mut self, key: &ArtKey) {
+        if let Some(index) = self.art_cache_order.iter().position(|cached| cached == key) {
+            self.art_cache_order.remove(index);
+        }
+        self.art_cache_order.push_back(key.clone());
+    }
+
+    /// The first embedded picture of `filename`, or the id3 error when the tag cannot be
+    /// read; a file without a tag simply has no picture.
+    fn extract_album_art_bytes(&self, filename: &str) -> Result<Option<(Vec<u8>, String)>, String> {
+        match Tag::read_from_path(filename) {
+            Ok(tag) => Ok(tag
+                .pictures()
+                .next()
+                .map(|picture| (picture.data.clone(), picture.mime_type.clone()))),
+            Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Ok(None),
+            Err(e) => Err(e.to_string()),
         }
     }
     pub fn next_item(&mut self) {
         match self.mode {
-            Mode::FileSelection => {
-                if !self.files.is_empty() {
-                    self.selected_file = (self.selected_file + 1) % self.files.len();
-                    self.current_file = self.files[self.selected_file].clone();
-                }
+            Mode::FileSelection if !self.files.is_empty() => {
+                self.selected_file = (self.selected_file + 1) % self.files.len();
+                self.current_file = self.files[self.selected_file].clone();
             }
             Mode::FieldSelection => {
                 self.selected_field = (self.selected_field + 1) % self.fields.len();
@@ -124,15 +691,13 @@ impl App {
 
     pub fn previous_item(&mut self) {
         match self.mode {
-            Mode::FileSelection => {
-                if !self.files.is_empty() {
-                    if self.selected_file > 0 {
-                        self.selected_file -= 1;
-                    } else {
-                        self.selected_file = self.files.len() - 1;
-                    }
-                    self.current_file = self.files[self.selected_file].clone();
+            Mode::FileSelection if !self.files.is_empty() => {
+                if self.selected_file > 0 {
+                    self.selected_file -= 1;
+                } else {
+                    self.selected_file = self.files.len() - 1;
                 }
+                self.current_file = self.files[self.selected_file].clone();
             }
             Mode::FieldSelection => {
                 if self.selected_field > 0 {
@@ -145,6 +710,40 @@ impl App {
         }
     }
 
+    pub fn toggle_file_selected(&mut self) {
+        let Some(file) = self.files.get(self.selected_file).cloned() else {
+            return;
+        };
+        if !self.selected_files.remove(&file) {
+            self.selected_files.insert(file);
+        }
+        self.next_item();
+    }
+
+    pub fn select_all_files(&mut self) {
+        self.selected_files = self.files.iter().cloned().collect();
+        self.message = format!("Selected {} files", self.selected_files.len());
+    }
+
+    pub fn clear_file_selection(&mut self) {
+        if !self.selected_files.is_empty() {
+            self.selected_files.clear();
+            self.message = "Selection cleared".to_string();
+        }
+    }
+
+    /// The files a batch operation applies to: the selection if there is one, else all.
+    fn batch_files(&self) -> Vec<String> {
+        if self.selected_files.is_empty() {
+            return self.files.clone();
+        }
+        self.files
+            .iter()
+            .filter(|file| self.selected_files.contains(*file))
+            .cloned()
+            .collect()
+    }
+
     pub fn start_field_selection(&mut self) {
         if !self.files.is_empty() {
             self.mode = Mode::FieldSelection;
@@ -152,122 +751,2986 @@ impl App {
         }
     }
 
+    /// Remembers whether the advanced fields are shown, for the next run.
+    pub fn save_state(&self) -> Result<(), Box<dyn Error>> {
+        State {
+            advanced_fields: self.advanced_fields,
+        }
+        .save()
+    }
+
+    pub fn toggle_advanced_fields(&mut self) {
+        self.advanced_fields = !self.advanced_fields;
+        self.fields = field_names(self.advanced_fields);
+        self.selected_field = self.selected_field.min(self.fields.len() - 1);
+    }
+
+    pub fn show_frame_info(&mut self) {
+        self.mode = Mode::FrameInfo;
+    }
+
+    pub fn close_frame_info(&mut self) {
+        self.mode = Mode::FieldSelection;
+    }
+
+    /// Moves the field selection to the next field the file has no value for, counting
+    /// unsaved edits, wrapping around the list.
+    pub fn next_empty_field(&mut self) {
+        let tag = Tag::read_from_path(&self.current_file).ok();
+        let count = self.fields.len();
+        let empty = (1..=count)
+            .map(|offset| (self.selected_field + offset) % count)
+            .find(|&index| {
+                let field = &self.fields[index];
+                let value = match self.buffered_edit(&self.current_file, field) {
+                    Some(edit) => edit.new.clone(),
+                    None => tag
+                        .as_ref()
+                        .map(|tag| field_value(tag, field))
+                        .unwrap_or_default(),
+                };
+                value.trim().is_empty()
+            });
+        match empty {
+            Some(index) => self.selected_field = index,
+            None => self.message = "Every field of this file has a value".to_string(),
+        }
+    }
+
+    pub fn toggle_quick_track(&mut self) {
+        self.quick_track = !self.quick_track;
+        self.message = if self.quick_track {
+            "Quick track entry on: select Track and press 1-9 to number each file".to_string()
+        } else {
+            "Quick track entry off".to_string()
+        };
+    }
+
+    /// With quick track entry on and Track selected, writes `digit` as the track number and
+    /// moves on to the next file.
+    pub fn quick_set_track(&mut self, digit: char) {
+        let Some(track) = digit.to_digit(10).filter(|track| *track > 0) else {
+            return;
+        };
+        if !self.quick_track || self.fields[self.selected_field] != "Track" {
+            return;
+        }
+
+        let file = self.current_file.clone();
+        if !is_writable(&file) {
+            self.message = format!("✗ {} is read-only, cannot edit its tags", file);
+            return;
+        }
+
+        // Only the number is typed, so keep whatever total the file already has
+        let value = match self
+            .tags_for_file(&file)
+            .and_then(|tag_info| tag_info.total_tracks)
+        {
+            Some(total) => format!("{}/{}", track, total),
+            None => track.to_string(),
+        };
+        // Buffered, checked against changes on disk, and so on, like a typed edit
+        if !self.write_field(&file, "Track", &value) {
+            return;
+        }
+        if self.selected_file + 1 < self.files.len() {
+            self.selected_file += 1;
+            self.current_file = self.files[self.selected_file].clone();
+        }
+        self.message = if self.buffer_writes {
+            format!(
+                "✎ Track {} on {}, not saved yet, now on {}",
+                track, file, self.current_file
+            )
+        } else {
+            format!(
+                "✓ Track {} on {}, now on {}",
+                track, file, self.current_file
+            )
+        };
+    }
+
+    /// Edits `field` of the selected file without going through the field list.
+    pub fn start_quick_edit(&mut self, field: &str) {
+        let Some(index) = self.fields.iter().position(|name| name == field) else {
+            return;
+        };
+        if self.files.is_empty() {
+            return;
+        }
+
+        self.selected_field = index;
+        self.start_editing();
+        self.quick_edit = self.mode == Mode::Editing;
+    }
+
+    /// Where editing goes back to once it is finished or cancelled.
+    fn leave_editing(&mut self) {
+        // A save held back because the file changed on disk is still to be asked about
+        if self.mode != Mode::Confirm {
+            self.mode = if self.quick_edit {
+                Mode::FileSelection
+            } else {
+                Mode::FieldSelection
+            };
+        }
+        self.quick_edit = false;
+        self.current_field = None;
+    }
+
     pub fn start_editing(&mut self) {
+        if !is_writable(&self.current_file) {
+            self.message = format!("✗ {} is read-only, cannot edit its tags", self.current_file);
+            return;
+        }
+
         self.mode = Mode::Editing;
         self.input_buffer.clear();
         self.current_field = Some(self.fields[self.selected_field].clone());
 
-        if let Ok(tag) = Tag::read_from_path(&self.current_file) {
-            match self.fields[self.selected_field].as_str() {
-                "Song Name" => self.input_buffer = tag.title().unwrap_or("").to_string(),
-                "Artist" => self.input_buffer = tag.artist().unwrap_or("").to_string(),
-                "Album" => self.input_buffer = tag.album().unwrap_or("").to_string(),
-                "Date" => self.input_buffer = tag.year().map(|y| y.to_string()).unwrap_or_default(),
-                "Track" => {
-                    self.input_buffer = tag.track().map(|t| t.to_string()).unwrap_or_default()
-                }
-                _ => {}
-            }
+        if let Some(edit) =
+            self.buffered_edit(&self.current_file, &self.fields[self.selected_field])
+        {
+            self.input_buffer = edit.new.clone();
+        } else {
+            self.input_buffer = self.field_on_disk();
         }
+        self.editing_original = self.input_buffer.clone();
+        self.discard_requested = false;
     }
 
-    pub fn finish_editing(&mut self) -> Result<(), Box<dyn Error>> {
+    /// The value the field being edited has in the file. It is read afresh, so that is
+    /// the state the edit is based on.
+    fn field_on_disk(&mut self) -> String {
+        let file = self.current_file.clone();
+        self.note_mtime(&file);
+        match (Tag::read_from_path(&file), &self.current_field) {
+            (Ok(tag), Some(field)) => field_value(&tag, field),
+            _ => String::new(),
+        }
+    }
+
+    /// Puts the value on disk back in the input, dropping what was typed so far but
+    /// staying in editing.
+    pub fn reset_field(&mut self) {
+        self.input_buffer = self.field_on_disk();
+        self.discard_requested = false;
         if let Some(field) = &self.current_field {
-            let result = modify_field(&self.current_file, field, &self.input_buffer);
+            self.message = format!("Reset {} to the value on disk", field);
+        }
+    }
+
+    /// Holds `value` for `field` of `file` until the next save, replacing any earlier
+    /// unsaved edit of the same field. Returns whether it was taken.
+    fn buffer_edit(&mut self, file: &str, field: &str, value: &str) -> bool {
+        // Catch what would fail on save now, while the value is still being worked on
+        if let Some(input) = field_input(field)
+            && !(input.is_valid)(value)
+        {
+            self.message = format!("✗ Error: expected {}", input.hint);
+            return false;
+        }
+
+        let existing = self
+            .buffered
+            .iter()
+            .position(|edit| edit.file == file && edit.field == field);
+        let old = match existing {
+            Some(index) => self.buffered[index].old.clone(),
+            None => match read_tag_for_write(file) {
+                Ok(tag) => field_value(&tag, field),
+                Err(e) => {
+                    self.message = format!("✗ Error: {}", e);
+                    return false;
+                }
+            },
+        };
+        if let Some(index) = existing {
+            self.buffered.remove(index);
+        }
+        // A warning given before this edit does not cover it
+        self.quit_requested = false;
+        self.last_values
+            .insert(field.to_string(), value.to_string());
+
+        // Typing the original value back is the same as not editing at all
+        if old == value {
+            self.message = format!("{} is back to its saved value", field);
+            return true;
+        }
+        let index = self
+            .buffered
+            .iter()
+            .position(|edit| edit.file.as_str() > file)
+            .unwrap_or(self.buffered.len());
+        self.buffered.insert(
+            index,
+            BufferedEdit {
+                file: file.to_string(),
+                field: field.to_string(),
+                old,
+                new: value.to_string(),
+                enabled: true,
+            },
+        );
+        self.message = format!(
+            "✎ {} set to '{}', not saved yet (W to review)",
+            field, value
+        );
+        self.dirty_since.get_or_insert_with(Instant::now);
+        if let Some(edits) = self.autosave_edits
+            && self.buffered.len() >= edits
+        {
+            self.autosave();
+        }
+        true
+    }
+
+    /// The unsaved edit of `field` on `file`, if any.
+    pub fn buffered_edit(&self, file: &str, field: &str) -> Option<&BufferedEdit> {
+        self.buffered
+            .iter()
+            .find(|edit| edit.file == file && edit.field == field)
+    }
+
+    pub fn has_buffered_edits(&self, file: &str) -> bool {
+        self.buffered.iter().any(|edit| edit.file == file)
+    }
+
+    /// Every unsaved edit, grouped by file, with whether the next save includes it.
+    pub fn pending_summary(&self) -> &[BufferedEdit] {
+        &self.buffered
+    }
+
+    pub fn open_review(&mut self) {
+        if self.buffered.is_empty() {
+            self.message = "No unsaved edits".to_string();
+            return;
+        }
+        self.review_position = self.review_position.min(self.buffered.len() - 1);
+        self.mode = Mode::Review;
+    }
+
+    pub fn close_review(&mut self) {
+        self.mode = Mode::FileSelection;
+    }
+
+    pub fn scroll_review(&mut self, scroll: Scroll) {
+        self.review_position =
+            scroll.apply(self.review_position, self.buffered.len(), self.view_height);
+    }
+
+    pub fn review_position(&self) -> usize {
+        self.review_position
+    }
+
+    /// Includes or leaves out the highlighted edit in the next save.
+    pub fn toggle_review_item(&mut self) {
+        if let Some(edit) = self.buffered.get_mut(self.review_position) {
+            edit.enabled = !edit.enabled;
+        }
+    }
+
+    /// Forgets the highlighted edit altogether.
+    pub fn drop_review_item(&mut self) {
+        if self.review_position >= self.buffered.len() {
+            return;
+        }
+        let edit = self.buffered.remove(self.review_position);
+        self.message = format!("Dropped {} edit on {}", edit.field, edit.file);
+        if self.buffered.is_empty() {
+            self.close_review();
+        } else {
+            self.review_position = self.review_position.min(self.buffered.len() - 1);
+        }
+    }
+
+    /// Writes every included edit. Left out and failed edits stay in the buffer.
+    pub fn save_all(&mut self) {
+        let (saved, failed) = self.write_buffered();
+        self.quit_requested = false;
+        self.close_review();
+        self.message = if failed == 0 {
+            format!("✓ Saved {} edits", saved)
+        } else {
+            format!(
+                "✗ Saved {} edits, {} failed and are still unsaved",
+                saved, failed
+            )
+        };
+    }
+
+    /// Writes every included buffered edit, keeping those that fail, and returns how
+    /// many were saved and how many failed.
+    fn write_buffered(&mut self) -> (usize, usize) {
+        let mut saved = 0;
+        let mut failed = 0;
+        let mut kept = Vec::new();
+        for edit in std::mem::take(&mut self.buffered) {
+            if !edit.enabled {
+                kept.push(edit);
+                continue;
+            }
+            let expected = self.read_mtimes.get(&edit.file).copied();
+            let result = modify_field(&edit.file, &edit.field, &edit.new, expected);
+            self.invalidate_file(&edit.file);
             match result {
                 Ok(_) => {
-                    self.message = format!("✓ Updated {} to '{}'", field, self.input_buffer);
+                    saved += 1;
+                    self.record(&edit.file, format!("set {} '{}'", edit.field, edit.new));
                 }
                 Err(e) => {
-                    self.message = format!("✗ Error: {}", e);
+                    // Kept for review: the file now reads as changed, so saving again
+                    // overwrites on purpose
+                    if e.is::<ChangedOnDisk>() {
+                        self.log(format!("{}, kept the edit of {}", e, edit.field));
+                    }
+                    failed += 1;
+                    kept.push(edit);
                 }
             }
         }
-        self.mode = Mode::FieldSelection;
-        self.current_field = None;
+        self.buffered = kept;
+        // Whatever is left over starts the timer again rather than retrying every tick
+        self.dirty_since = (!self.buffered.is_empty()).then(Instant::now);
+        (saved, failed)
+    }
+
+    /// Saves the buffered edits on their own, unless they are being reviewed.
+    fn autosave(&mut self) {
+        if self.mode == Mode::Review {
+            return;
+        }
+        let (saved, failed) = self.write_buffered();
+        if saved == 0 && failed == 0 {
+            return;
+        }
+        self.log(format!("auto-saved {} edits", saved));
+        self.message = if failed == 0 {
+            format!("✓ Auto-saved {} edits", saved)
+        } else {
+            format!(
+                "✗ Auto-saved {} edits, {} failed and are still unsaved",
+                saved, failed
+            )
+        };
+    }
+
+    fn autosave_due(&self) -> bool {
+        match (self.autosave_interval, self.dirty_since) {
+            (Some(interval), Some(since)) => since.elapsed() >= interval,
+            _ => false,
+        }
+    }
+
+    /// False the first time while edits are unsaved, warning instead of quitting.
+    pub fn confirm_quit(&mut self) -> bool {
+        if self.buffered.is_empty() || self.quit_requested {
+            return true;
+        }
+        self.quit_requested = true;
+        self.message = format!(
+            "✗ {} unsaved edits: W to review and save, q or Ctrl+C again to quit without them",
+            self.buffered.len()
+        );
+        false
+    }
+
+    pub fn finish_editing(&mut self) -> Result<(), Box<dyn Error>> {
+        self.save_field();
+        self.leave_editing();
         Ok(())
     }
 
-    pub fn cancel_editing(&mut self) {
-        self.mode = Mode::FieldSelection;
-        self.current_field = None;
-        self.message = "Edit cancelled".to_string();
+    /// Saves the field being edited, reporting the outcome. Returns whether it was written.
+    fn save_field(&mut self) -> bool {
+        let Some(field) = self.current_field.clone() else {
+            return false;
+        };
+        let (file, value) = (self.current_file.clone(), self.input_buffer.clone());
+        self.write_field(&file, &field, &value)
     }
 
-    pub fn back_to_files(&mut self) {
+    /// Writes one field, or buffers it when writes are buffered, and says how it went.
+    fn write_field(&mut self, file: &str, field: &str, value: &str) -> bool {
+        if self.buffer_writes {
+            return self.buffer_edit(file, field, value);
+        }
+        let expected = self.read_mtimes.get(file).copied();
+        self.apply_field(file, field, value, expected)
+    }
+
+    /// Writes one field straight away, first checking the file still has the modification
+    /// time `expected`; if not, asks whether to overwrite the other program's change.
+    fn apply_field(
+        &mut self,
+        file: &str,
+        field: &str,
+        value: &str,
+        expected: Option<SystemTime>,
+    ) -> bool {
+        match modify_field(file, field, value, expected) {
+            Err(e) if e.is::<ChangedOnDisk>() => {
+                self.pending = Some(PendingAction::Overwrite {
+                    file: file.to_string(),
+                    field: field.to_string(),
+                    value: value.to_string(),
+                });
+                self.mode = Mode::Confirm;
+                false
+            }
+            Ok(_) => {
+                self.message = format!("✓ Updated {} to '{}'", field, value);
+                self.invalidate_file(file);
+                self.record(file, format!("set {} '{}'", field, value));
+                self.last_values
+                    .insert(field.to_string(), value.to_string());
+                true
+            }
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Opens the marked files (or all of them) as a table to type tags into directly.
+    pub fn open_table(&mut self) {
+        let (files, read_only): (Vec<String>, Vec<String>) = self
+            .batch_files()
+            .into_iter()
+            .partition(|file| is_writable(file));
+        let rows: Vec<TableRow> = files
+            .into_iter()
+            .filter_map(|file| {
+                self.note_mtime(&file);
+                let tag = match Tag::read_from_path(&file) {
+                    Ok(tag) => tag,
+                    Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Tag::new(),
+                    Err(_) => return None,
+                };
+                // Unsaved edits are what the user last saw, so they are the starting point
+                let saved: Vec<String> = TABLE_COLUMNS
+                    .iter()
+                    .map(|field| match self.buffered_edit(&file, field) {
+                        Some(edit) => edit.new.clone(),
+                        None => field_value(&tag, field),
+                    })
+                    .collect();
+                Some(TableRow {
+                    file,
+                    values: saved.clone(),
+                    saved,
+                })
+            })
+            .collect();
+        if rows.is_empty() {
+            self.message = if read_only.is_empty() {
+                "No readable files to edit".to_string()
+            } else {
+                "✗ No writable files to edit".to_string()
+            };
+            return;
+        }
+
+        let row = rows
+            .iter()
+            .position(|row| row.file == self.current_file)
+            .unwrap_or(0);
+        self.table = Some(TableEdit {
+            rows,
+            row,
+            column: 0,
+        });
+        self.mode = Mode::TableEdit;
+        self.message = match read_only.len() {
+            0 => "Tab between cells, Enter saves the row".to_string(),
+            skipped => format!(
+                "Tab between cells, Enter saves the row (left out {} read-only files)",
+                skipped
+            ),
+        };
+    }
+
+    /// Opens every field of the current file for editing at once.
+    pub fn open_form(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        let file = self.current_file.clone();
+        if !is_writable(&file) {
+            self.message = format!("✗ {} is read-only, cannot edit its tags", file);
+            return;
+        }
+        self.note_mtime(&file);
+        let tag = match Tag::read_from_path(&file) {
+            Ok(tag) => tag,
+            Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Tag::new(),
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+                return;
+            }
+        };
+        let saved: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| match self.buffered_edit(&file, field) {
+                Some(edit) => edit.new.clone(),
+                None => field_value(&tag, field),
+            })
+            .collect();
+        self.form = Some(EditForm {
+            file,
+            fields: self.fields.clone(),
+            values: saved.clone(),
+            saved,
+            focus: 0,
+        });
+        self.mode = Mode::Form;
+        self.message = "Tab between fields, Enter saves them all".to_string();
+    }
+
+    /// Leaves the form, dropping whatever was typed but not saved.
+    pub fn close_form(&mut self) {
+        let unsaved = self.form.take().map_or(0, |form| {
+            (0..form.fields.len())
+                .filter(|index| form.is_changed(*index))
+                .count()
+        });
+        if unsaved > 0 {
+            self.message = format!("Left the form, {} fields were not saved", unsaved);
+        }
         self.mode = Mode::FileSelection;
-        self.message = "Select a file to edit".to_string();
     }
 
-    pub fn files(&self) -> &[String] {
-        &self.files
+    pub fn form(&self) -> Option<&EditForm> {
+        self.form.as_ref()
     }
 
-    pub fn selected_file(&self) -> usize {
-        self.selected_file
+    /// Moves the focus between fields, wrapping around at either end.
+    pub fn move_form_focus(&mut self, step: isize) {
+        if let Some(form) = self.form.as_mut() {
+            let fields = form.fields.len() as isize;
+            form.focus = (form.focus as isize + step).rem_euclid(fields) as usize;
+        }
     }
 
-    pub fn fields(&self) -> &[String] {
-        &self.fields
+    pub fn push_form_char(&mut self, c: char) {
+        let Some(form) = self.form.as_mut() else {
+            return;
+        };
+        if let Some(input) = field_input(&form.fields[form.focus])
+            && !(input.accepts)(c)
+        {
+            return;
+        }
+        form.values[form.focus].push(c);
     }
 
-    pub fn selected_field(&self) -> usize {
-        self.selected_field
+    pub fn pop_form_char(&mut self) {
+        if let Some(form) = self.form.as_mut() {
+            form.values[form.focus].pop();
+        }
     }
 
-    pub fn input_buffer(&self) -> &str {
-        &self.input_buffer
+    /// Writes every changed field of the form and closes it. A field that fails to save
+    /// keeps the form open on it.
+    pub fn save_form(&mut self) {
+        let Some(mut form) = self.form.take() else {
+            return;
+        };
+        let mut saved = 0;
+        for index in 0..form.fields.len() {
+            if !form.is_changed(index) {
+                continue;
+            }
+            let (field, value) = (form.fields[index].clone(), form.values[index].clone());
+            if !self.write_field(&form.file, &field, &value) {
+                form.focus = index;
+                self.form = Some(form);
+                return;
+            }
+            form.saved[index] = value;
+            saved += 1;
+        }
+        self.mode = Mode::FileSelection;
+        match saved {
+            0 => self.message = "Nothing to save".to_string(),
+            // write_field already said what it wrote
+            1 => {}
+            _ => self.message = format!("✓ Saved {} fields of {}", saved, form.file),
+        }
     }
 
-    pub fn current_field(&self) -> Option<&String> {
-        self.current_field.as_ref()
+    /// Marks a field of the form as saved after it was overwritten on confirmation, and
+    /// goes on saving the fields after it.
+    fn resume_form(&mut self, file: &str, field: &str, value: String) {
+        let Some(form) = self.form.as_mut().filter(|form| form.file == file) else {
+            return;
+        };
+        if let Some(index) = form.fields.iter().position(|f| f == field) {
+            form.saved[index] = value;
+        }
+        if (0..form.fields.len()).any(|index| form.is_changed(index)) {
+            self.save_form();
+        } else {
+            self.form = None;
+            self.mode = Mode::FileSelection;
+        }
     }
 
-    pub fn mode(&self) -> &Mode {
-        &self.mode
+    /// Leaves the table, dropping rows typed into but not saved.
+    pub fn close_table(&mut self) {
+        let unsaved = self.table.take().map_or(0, |table| {
+            table.rows.iter().filter(|row| row.is_dirty()).count()
+        });
+        if unsaved > 0 {
+            self.message = format!("Left the table, {} rows were not saved", unsaved);
+        }
+        self.mode = Mode::FileSelection;
     }
 
-    pub fn message(&self) -> &str {
-        &self.message
+    pub fn table(&self) -> Option<&TableEdit> {
+        self.table.as_ref()
     }
 
-    pub fn set_message(&mut self, message: String) {
-        self.message = message;
+    pub fn scroll_table(&mut self, scroll: Scroll) {
+        let page = self.view_height;
+        if let Some(table) = self.table.as_mut() {
+            table.row = scroll.apply(table.row, table.rows.len(), page);
+        }
     }
 
-    pub fn push_to_buffer(&mut self, c: char) {
-        self.input_buffer.push(c);
+    /// Moves between columns, wrapping around at either end.
+    pub fn move_table_column(&mut self, step: isize) {
+        if let Some(table) = self.table.as_mut() {
+            let columns = TABLE_COLUMNS.len() as isize;
+            table.column = (table.column as isize + step).rem_euclid(columns) as usize;
+        }
     }
 
-    pub fn pop_from_buffer(&mut self) {
-        self.input_buffer.pop();
+    pub fn push_table_char(&mut self, c: char) {
+        let Some(table) = self.table.as_mut() else {
+            return;
+        };
+        if let Some(input) = field_input(TABLE_COLUMNS[table.column])
+            && !(input.accepts)(c)
+        {
+            return;
+        }
+        table.rows[table.row].values[table.column].push(c);
     }
 
-    pub fn tags_for_file(&self, filename: &str) -> Option<TagInfo> {
-        match Tag::read_from_path(filename) {
-            Ok(tag) => Some(TagInfo {
-                title: tag
-                    .title()
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| "Unknown".to_string()),
-                artist: tag
-                    .artist()
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| "Unknown".to_string()),
-                album: tag
-                    .album()
-                    .map(|s| s.to_string())
-                    .unwrap_or_else(|| "Unknown".to_string()),
-                year: tag
-                    .date_recorded()
-                    .map(|y| y.to_string())
-                    .unwrap_or_else(|| "Unknown".to_string()),
-                track: tag
-                    .track()
-                    .map(|t| t.to_string())
-                    .unwrap_or_else(|| "Unknown".to_string()),
-            }),
-            Err(_) => None,
+    pub fn pop_table_char(&mut self) {
+        if let Some(table) = self.table.as_mut() {
+            table.rows[table.row].values[table.column].pop();
         }
     }
+
+    /// Saves the changed cells of the current row and moves to the next one. A cell that
+    /// fails to save keeps the row where it is.
+    pub fn commit_table_row(&mut self) {
+        let Some(mut table) = self.table.take() else {
+            return;
+        };
+        let row = &mut table.rows[table.row];
+        let mut saved = 0;
+        let mut ok = true;
+        for (column, field) in TABLE_COLUMNS.iter().enumerate() {
+            if row.values[column] == row.saved[column] {
+                continue;
+            }
+            let (file, value) = (row.file.clone(), row.values[column].clone());
+            if !self.write_field(&file, field, &value) {
+                ok = false;
+                break;
+            }
+            row.saved[column] = value;
+            saved += 1;
+        }
+        if ok {
+            if saved > 1 {
+                self.message = format!("✓ Saved {} fields of {}", saved, row.file);
+            }
+            if table.row + 1 < table.rows.len() {
+                table.row += 1;
+            }
+        }
+        self.table = Some(table);
+    }
+
+    /// Marks a cell as saved after it was overwritten on confirmation, and goes on saving
+    /// the rest of its row.
+    fn resume_table_row(&mut self, file: &str, field: &str, value: String) {
+        let Some(table) = self.table.as_mut() else {
+            return;
+        };
+        let row = &mut table.rows[table.row];
+        if row.file != file {
+            return;
+        }
+        if let Some(column) = TABLE_COLUMNS.iter().position(|f| *f == field) {
+            row.saved[column] = value;
+        }
+        self.commit_table_row();
+    }
+
+    /// Saves the field and goes on to the same field of the file `step` places away,
+    /// staying in editing. A value that fails to save keeps the current file open.
+    pub fn edit_adjacent_file(&mut self, step: isize) {
+        if !self.save_field() {
+            if self.mode == Mode::Confirm {
+                self.leave_editing();
+            }
+            return;
+        }
+        let Some(index) = self
+            .selected_file
+            .checked_add_signed(step)
+            .filter(|index| *index < self.files.len())
+        else {
+            self.leave_editing();
+            return;
+        };
+
+        self.selected_file = index;
+        self.current_file = self.files[index].clone();
+        // Stop on a read-only file rather than leave the editor on it with nothing to save to
+        if !is_writable(&self.current_file) {
+            self.leave_editing();
+            self.message = format!("✗ {} is read-only, cannot edit its tags", self.current_file);
+            return;
+        }
+        self.start_editing();
+    }
+
+    /// The value last saved for the field being edited, if it differs from the buffer.
+    pub fn last_value(&self) -> Option<&str> {
+        let field = self.current_field.as_ref()?;
+        self.last_values
+            .get(field)
+            .map(|value| value.as_str())
+            .filter(|value| *value != self.input_buffer)
+    }
+
+    pub fn discard_requested(&self) -> bool {
+        self.discard_requested
+    }
+
+    pub fn reuse_last_value(&mut self) {
+        if let Some(value) = self.last_value().map(|value| value.to_string()) {
+            self.input_buffer = value;
+            self.discard_requested = false;
+        }
+    }
+
+    /// Leaves editing without saving, asking first when the value was changed.
+    pub fn cancel_editing(&mut self) {
+        if self.input_buffer != self.editing_original && !self.discard_requested {
+            self.discard_requested = true;
+            self.message = "Discard changes? Esc again to discard, Enter to save".to_string();
+            return;
+        }
+        self.leave_editing();
+        self.message = "Edit cancelled".to_string();
+    }
+
+    /// Embeds covers from a folder of `<album>.jpg` files into every matching track.
+    fn embed_art_by_album(&mut self, art_dir: &str) {
+        let art_dir = Path::new(art_dir.trim());
+        if !art_dir.is_dir() {
+            self.message = format!("✗ Not a directory: {}", art_dir.display());
+            return;
+        }
+
+        let mut outcome = BatchOutcome::default();
+        let mut unmatched = 0;
+        for file in self.batch_files() {
+            let Some(cover) = album_cover(&file, art_dir) else {
+                unmatched += 1;
+                continue;
+            };
+            let expected = self.read_mtimes.get(&file).copied();
+            let result = fs::read(&cover)
+                .map_err(Box::<dyn Error>::from)
+                .and_then(|data| embed_art(&file, data, mime_for_image(&cover), expected));
+            if self.count_write(&mut outcome, &file, result) {
+                outcome.done += 1;
+                self.record(&file, format!("embedded {}", cover.display()));
+            }
+        }
+
+        let summary = format!("{} matched, {} unmatched", outcome.done, unmatched);
+        self.log(format!(
+            "embedded covers by album from {}: {}",
+            art_dir.display(),
+            summary
+        ));
+        self.message = outcome.message(format!(
+            "Embedded covers from {}: {}",
+            art_dir.display(),
+            summary
+        ));
+    }
+
+    pub fn embed_sidecar_art(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+
+        let Some(sidecar) = find_sidecar_art(&self.current_file) else {
+            self.message = "No cover image found next to this file".to_string();
+            return;
+        };
+
+        let file = self.current_file.clone();
+        let expected = self.read_mtimes.get(&file).copied();
+        let result = fs::read(&sidecar)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|data| embed_art(&file, data, mime_for_image(&sidecar), expected));
+        self.invalidate_file(&file);
+        match result {
+            Ok(_) => {
+                self.message = format!(
+                    "✓ Embedded {} into {}",
+                    sidecar.display(),
+                    self.current_file
+                );
+                self.record(&file, format!("embedded {}", sidecar.display()));
+            }
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+            }
+        }
+    }
+
+    /// Makes the next embedded picture of the selected file its front cover.
+    pub fn next_picture(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        let file = self.current_file.clone();
+        let result = next_picture(&file, self.read_mtimes.get(&file).copied());
+        self.invalidate_file(&file);
+        match result {
+            Ok(types) => {
+                let types: Vec<String> = types.iter().map(|kind| kind.to_string()).collect();
+                self.message = format!("✓ Pictures now {}", types.join(", "));
+                self.record(&file, format!("reordered pictures to {}", types.join(", ")));
+            }
+            Err(e) => self.message = format!("✗ Error: {}", e),
+        }
+    }
+
+    /// Gives the front picture of the selected file the next picture type.
+    pub fn cycle_picture_type(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        let file = self.current_file.clone();
+        let result = cycle_picture_type(&file, self.read_mtimes.get(&file).copied());
+        self.invalidate_file(&file);
+        match result {
+            Ok(picture_type) => {
+                self.message = format!("✓ First picture is now: {}", picture_type);
+                self.record(&file, format!("set the first picture to {}", picture_type));
+            }
+            Err(e) => self.message = format!("✗ Error: {}", e),
+        }
+    }
+
+    pub fn start_prompt(&mut self, kind: PromptKind) {
+        if self.files.is_empty() {
+            return;
+        }
+        self.mode = Mode::Prompt;
+        self.prompt = Some(kind);
+        self.input_buffer.clear();
+        self.completions.clear();
+
+        if kind == PromptKind::Rename {
+            self.input_buffer = Path::new(&self.current_file)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+        }
+        if kind == PromptKind::Normalize {
+            self.input_buffer = NORMALIZE_TEMPLATE.to_string();
+        }
+    }
+
+    pub fn finish_prompt(&mut self) {
+        let input = std::mem::take(&mut self.input_buffer);
+        self.completions.clear();
+        self.mode = Mode::FileSelection;
+
+        // Each step may chain into the next prompt or a confirmation
+        match self.prompt.take() {
+            Some(PromptKind::ArtPath) => self.embed_art_from_path(&input),
+            Some(PromptKind::ReplaceField) => {
+                let field = self
+                    .fields
+                    .iter()
+                    .find(|field| field.eq_ignore_ascii_case(input.trim()))
+                    .cloned();
+                match field {
+                    Some(field) => {
+                        self.replace_draft = Some(FindReplace {
+                            field,
+                            search: String::new(),
+                            replacement: String::new(),
+                            case_insensitive: false,
+                            whole_field: false,
+                        });
+                        self.start_prompt(PromptKind::ReplaceSearch);
+                    }
+                    None => self.message = format!("✗ Unknown field '{}'", input),
+                }
+            }
+            Some(PromptKind::ReplaceSearch) => {
+                if input.is_empty() {
+                    self.replace_draft = None;
+                    self.message = "✗ Nothing to search for".to_string();
+                } else if let Some(draft) = self.replace_draft.as_mut() {
+                    draft.search = input;
+                    self.start_prompt(PromptKind::ReplaceWith);
+                }
+            }
+            Some(PromptKind::ReplaceWith) => {
+                if let Some(mut find) = self.replace_draft.take() {
+                    find.replacement = input;
+                    self.preview_replace(find);
+                }
+            }
+            Some(PromptKind::Rename) => self.rename_current_file(&input),
+            Some(PromptKind::RenameTemplate) => self.preview_rename_template(&input),
+            Some(PromptKind::Normalize) => self.preview_normalize(&input),
+            Some(PromptKind::AlbumArtDir) => self.embed_art_by_album(&input),
+            Some(PromptKind::CopyByTrack) => self.preview_copy_by_track(&input),
+            Some(PromptKind::ExportJson) => self.export_json(&input),
+            Some(PromptKind::ImportJson) => self.preview_import_json(&input),
+            Some(PromptKind::Transform) => self.preview_transform(&input),
+            None => {}
+        }
+    }
+
+    pub fn cancel_prompt(&mut self) {
+        self.prompt = None;
+        self.replace_draft = None;
+        self.input_buffer.clear();
+        self.completions.clear();
+        self.mode = Mode::FileSelection;
+        self.message = "Cancelled".to_string();
+    }
+
+    /// Completes the prompt input, cycling through the matches on repeated calls.
+    pub fn complete_prompt(&mut self) {
+        let cycling = self.completions.len() > 1
+            && self.completions.get(self.completion_index) == Some(&self.input_buffer);
+
+        if cycling {
+            self.completion_index = (self.completion_index + 1) % self.completions.len();
+        } else {
+            self.completions = match self.prompt {
+                Some(PromptKind::ReplaceField) => {
+                    let typed = self.input_buffer.to_lowercase();
+                    self.fields
+                        .iter()
+                        .filter(|field| field.to_lowercase().starts_with(&typed))
+                        .cloned()
+                        .collect()
+                }
+                Some(PromptKind::Transform) => {
+                    let typed = self.input_buffer.to_lowercase();
+                    self.transforms
+                        .iter()
+                        .filter(|rule| rule.name.to_lowercase().starts_with(&typed))
+                        .map(|rule| rule.name.clone())
+                        .collect()
+                }
+                Some(PromptKind::ArtPath)
+                | Some(PromptKind::AlbumArtDir)
+                | Some(PromptKind::CopyByTrack) => complete_path(&self.input_buffer),
+                _ => Vec::new(),
+            };
+            self.completion_index = 0;
+        }
+
+        if let Some(completion) = self.completions.get(self.completion_index) {
+            self.input_buffer = completion.clone();
+        }
+    }
+
+    /// Looks for album folders whose tracks disagree on the album fields and asks
+    /// whether to give each track the most common value.
+    pub fn preview_unify_albums(&mut self) {
+        let conflicts = album_conflicts(&self.batch_files());
+        if conflicts.is_empty() {
+            self.message = format!(
+                "✓ The tracks of each folder agree on {}",
+                ALBUM_FIELDS.join(", ")
+            );
+            return;
+        }
+        self.note_unread_mtimes(
+            conflicts
+                .iter()
+                .flat_map(|conflict| &conflict.values)
+                .flat_map(|(_, files)| files),
+        );
+        self.request_confirmation(PendingAction::UnifyAlbums { conflicts });
+    }
+
+    /// Looks for folders that are compilations by the look of their tags and asks
+    /// whether to mark their tracks as such.
+    pub fn preview_compilations(&mut self) {
+        let compilations = detect_compilations(&self.batch_files());
+        if compilations.is_empty() {
+            self.message = "No untagged compilations found".to_string();
+            return;
+        }
+        self.note_unread_mtimes(
+            compilations
+                .iter()
+                .flat_map(|compilation| &compilation.files),
+        );
+        self.request_confirmation(PendingAction::MarkCompilations { compilations });
+    }
+
+    /// Asks for the transform to apply, when any are configured.
+    pub fn start_transform(&mut self) {
+        if self.transforms.is_empty() {
+            self.message =
+                "✗ No transforms configured, see [[transforms]] in the README".to_string();
+        } else {
+            self.start_prompt(PromptKind::Transform);
+        }
+    }
+
+    /// Runs the transform called `name` over the marked files, or the current one, and
+    /// asks for confirmation of what it would change.
+    fn preview_transform(&mut self, name: &str) {
+        let Some(rule) = self
+            .transforms
+            .iter()
+            .find(|rule| rule.name.eq_ignore_ascii_case(name.trim()))
+        else {
+            self.message = format!("✗ Unknown transform '{}'", name);
+            return;
+        };
+        let files = if self.selected_files.is_empty() {
+            vec![self.current_file.clone()]
+        } else {
+            self.batch_files()
+        };
+        let changes: Vec<_> = files
+            .into_iter()
+            .filter_map(|file| {
+                let tag = Tag::read_from_path(&file).ok()?;
+                let value = field_value(&tag, rule.field);
+                let new = apply_transform(&value, rule);
+                (new != value).then_some((file, value, new))
+            })
+            .collect();
+
+        if changes.is_empty() {
+            self.message = format!("{} changes nothing", rule.name);
+            return;
+        }
+        let (name, field) = (rule.name.clone(), rule.field.to_string());
+        self.note_unread_mtimes(changes.iter().map(|(file, _, _)| file));
+        self.request_confirmation(PendingAction::Transform {
+            name,
+            field,
+            changes,
+        });
+    }
+
+    /// Works out which files a find-and-replace would touch and asks for confirmation.
+    fn preview_replace(&mut self, find: FindReplace) {
+        let affected: Vec<_> = self
+            .batch_files()
+            .iter()
+            .filter_map(|file| {
+                let tag = Tag::read_from_path(file).ok()?;
+                let value = field_value(&tag, &find.field);
+                let replaced = find.apply(&value)?;
+                (replaced != value).then(|| (file.clone(), replaced))
+            })
+            .collect();
+
+        self.note_unread_mtimes(affected.iter().map(|(file, _)| file));
+        self.request_confirmation(PendingAction::Replace { find, affected });
+    }
+
+    pub fn confirm_pending(&mut self) {
+        self.mode = Mode::FileSelection;
+        match self.pending.take() {
+            Some(PendingAction::Replace { find, affected }) => {
+                let mut outcome = BatchOutcome::default();
+                for (file, value) in affected {
+                    let expected = self.read_mtimes.get(&file).copied();
+                    let result = modify_field(&file, &find.field, &value, expected);
+                    if self.count_write(&mut outcome, &file, result) {
+                        outcome.done += 1;
+                        self.record(&file, format!("set {} '{}'", find.field, value));
+                    }
+                }
+                self.message =
+                    outcome.message(format!("Replaced {} in {} files", find.field, outcome.done));
+            }
+            Some(PendingAction::SwapArtistTitle {
+                file,
+                artist,
+                title,
+            }) => {
+                let expected = self.read_mtimes.get(&file).copied();
+                let result = modify_field(&file, "Artist", &title, expected)
+                    .and_then(|_| modify_field(&file, "Song Name", &artist, None));
+                self.invalidate_file(&file);
+                match result {
+                    Ok(_) => {
+                        self.message = format!("✓ Swapped Artist and Title on {}", file);
+                        self.record(
+                            &file,
+                            format!("swapped Artist '{}' and Title '{}'", artist, title),
+                        );
+                    }
+                    Err(e) => self.message = format!("✗ Error: {}", e),
+                }
+            }
+            Some(PendingAction::Transform {
+                name,
+                field,
+                changes,
+            }) => {
+                let mut outcome = BatchOutcome::default();
+                for (file, _, value) in changes {
+                    let expected = self.read_mtimes.get(&file).copied();
+                    let result = modify_field(&file, &field, &value, expected);
+                    if self.count_write(&mut outcome, &file, result) {
+                        outcome.done += 1;
+                        self.record(&file, format!("set {} '{}' ({})", field, value, name));
+                    }
+                }
+                self.message =
+                    outcome.message(format!("Applied {} to {} files", name, outcome.done));
+            }
+            Some(PendingAction::UnifyAlbums { conflicts }) => {
+                let mut outcome = BatchOutcome::default();
+                for conflict in &conflicts {
+                    let target = conflict.majority();
+                    let files = conflict
+                        .values
+                        .iter()
+                        .filter(|(value, _)| value != target)
+                        .flat_map(|(_, files)| files);
+                    for file in files {
+                        let expected = self.read_mtimes.get(file).copied();
+                        let result = modify_field(file, conflict.field, target, expected);
+                        if self.count_write(&mut outcome, file, result) {
+                            outcome.done += 1;
+                            self.record(file, format!("set {} '{}'", conflict.field, target));
+                        }
+                    }
+                }
+                self.message = outcome.message(format!("Unified {} fields", outcome.done));
+            }
+            Some(PendingAction::MarkCompilations { compilations }) => {
+                let mut outcome = BatchOutcome::default();
+                for file in compilations
+                    .iter()
+                    .flat_map(|compilation| &compilation.files)
+                {
+                    let result = mark_compilation(file, self.read_mtimes.get(file).copied());
+                    if self.count_write(&mut outcome, file, result) {
+                        outcome.done += 1;
+                        self.record(
+                            file,
+                            format!("set Album Artist '{}', compilation", VARIOUS_ARTISTS),
+                        );
+                    }
+                }
+                self.message = outcome.message(format!(
+                    "Marked {} files as compilation tracks",
+                    outcome.done
+                ));
+            }
+            Some(PendingAction::Overwrite { file, field, value }) => {
+                let written = self.apply_field(&file, &field, &value, None);
+                if self.table.is_some() {
+                    self.mode = Mode::TableEdit;
+                    if written {
+                        self.resume_table_row(&file, &field, value);
+                    }
+                } else if self.form.is_some() {
+                    self.mode = Mode::Form;
+                    if written {
+                        self.resume_form(&file, &field, value);
+                    }
+                }
+            }
+            Some(PendingAction::ImportJson { source, plan }) => {
+                let mut outcome = BatchOutcome::default();
+                for (file, edits) in plan.changes {
+                    let result = import_json(&file, &edits, self.read_mtimes.get(&file).copied());
+                    if self.count_write(&mut outcome, &file, result) {
+                        outcome.done += 1;
+                        let what: Vec<String> =
+                            edits.iter().map(|edit| edit.describe().0).collect();
+                        self.record(
+                            &file,
+                            format!("imported {} from {}", what.join(", "), source),
+                        );
+                    }
+                }
+                self.message =
+                    outcome.message(format!("Imported tags into {} files", outcome.done));
+            }
+            Some(PendingAction::CopyByTrack { matches, .. }) => {
+                let mut outcome = BatchOutcome::default();
+                for TrackMatch {
+                    source,
+                    target,
+                    edits,
+                } in matches
+                {
+                    let result = self.write_fields(&target, &edits);
+                    if self.count_write(&mut outcome, &target, result) {
+                        outcome.done += 1;
+                        let fields: Vec<&str> =
+                            edits.iter().map(|(field, _, _)| field.as_str()).collect();
+                        self.record(
+                            &target,
+                            format!("copied {} from {}", fields.join(", "), source),
+                        );
+                    }
+                }
+                self.message = outcome.message(format!("Copied tags to {} files", outcome.done));
+            }
+            Some(PendingAction::TrackTotals { changes, .. }) => {
+                let mut outcome = BatchOutcome::default();
+                for (file, _, new) in changes {
+                    let Some(new) = new else {
+                        continue;
+                    };
+                    let expected = self.read_mtimes.get(&file).copied();
+                    let result = modify_field(&file, "Track", &new, expected);
+                    if self.count_write(&mut outcome, &file, result) {
+                        outcome.done += 1;
+                        self.record(&file, format!("set Track '{}'", new));
+                    }
+                }
+                self.message =
+                    outcome.message(format!("Set the track total on {} files", outcome.done));
+            }
+            Some(PendingAction::StripTags { file }) => {
+                let result = strip_all_tags(&file, self.read_mtimes.get(&file).copied());
+                self.invalidate_file(&file);
+                match result {
+                    Ok(true) => {
+                        self.message = format!("✓ Stripped all tags from {}", file);
+                        self.record(&file, "stripped all tags".to_string());
+                    }
+                    Ok(false) => self.message = format!("{} has no tags to strip", file),
+                    Err(e) => self.message = format!("✗ Error: {}", e),
+                }
+            }
+            Some(PendingAction::RenameAll {
+                renames,
+                tag_changes,
+            }) => {
+                let mut outcome = BatchOutcome::default();
+                let mut renamed = Vec::new();
+                for (old, target, problem) in renames {
+                    if problem.is_some() {
+                        continue;
+                    }
+                    // The new name comes from the edited tags, so a failed edit stops the rename
+                    if let Some(changes) = tag_changes.get(&old) {
+                        let result = self.write_fields(&old, changes);
+                        if !self.count_write(&mut outcome, &old, result) {
+                            continue;
+                        }
+                        for (field, previous, value) in changes {
+                            self.record(
+                                &old,
+                                format!("set {} '{}' → '{}'", field, previous, value),
+                            );
+                        }
+                    }
+                    if old == target {
+                        outcome.done += 1;
+                        continue;
+                    }
+                    match fs::rename(&old, &target) {
+                        Ok(_) => {
+                            outcome.done += 1;
+                            self.track_rename(&old, &target);
+                            self.record(&target, format!("renamed from {}", old));
+                            renamed.push((old, target));
+                        }
+                        Err(e) => {
+                            self.log(format!("could not rename {}: {}", old, e));
+                            outcome.fail(e.to_string());
+                        }
+                    }
+                }
+                if !renamed.is_empty() {
+                    self.undo_stack.push(Undo::Rename(renamed));
+                }
+                self.sort_files();
+                let verb = if tag_changes.is_empty() {
+                    "Renamed"
+                } else {
+                    "Normalized"
+                };
+                self.message = outcome.message(format!("{} {} files", verb, outcome.done));
+            }
+            Some(PendingAction::RemoveArt { affected }) => {
+                let mut outcome = BatchOutcome::default();
+                let mut reclaimed = 0;
+                for (file, _) in affected {
+                    let mut removed = None;
+                    let result = remove_art(&file, self.read_mtimes.get(&file).copied())
+                        .map(|saved| removed = saved);
+                    if self.count_write(&mut outcome, &file, result)
+                        && let Some(saved) = removed
+                    {
+                        outcome.done += 1;
+                        reclaimed += saved;
+                        self.record(&file, "removed album art".to_string());
+                    }
+                }
+                self.message = outcome.message(format!(
+                    "Removed art from {} files, reclaimed {}",
+                    outcome.done,
+                    format_size(reclaimed)
+                ));
+            }
+            None => {}
+        }
+    }
+
+    /// Writes the (field, old, new) `edits` to `file` as one step of a batch, the first
+    /// checked against the modification time the file was read at.
+    fn write_fields(
+        &self,
+        file: &str,
+        edits: &[(String, String, String)],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut expected = self.read_mtimes.get(file).copied();
+        edits
+            .iter()
+            .try_for_each(|(field, _, new)| modify_field(file, field, new, expected.take()))
+    }
+
+    /// Notes in `outcome` why writing `file` as part of a batch failed, if it did, and
+    /// drops what was cached about the file. Returns whether the write went through.
+    fn count_write(
+        &mut self,
+        outcome: &mut BatchOutcome,
+        file: &str,
+        result: Result<(), Box<dyn Error>>,
+    ) -> bool {
+        self.invalidate_file(file);
+        match result {
+            Ok(_) => true,
+            Err(e) if e.is::<ChangedOnDisk>() => {
+                self.log(format!("{}, skipped it", e));
+                outcome.changed_on_disk.push(file.to_string());
+                false
+            }
+            Err(e) => {
+                self.log(format!("could not write {}: {}", file, e));
+                outcome.fail(e.to_string());
+                false
+            }
+        }
+    }
+
+    /// Analyses the loudness of the marked files (or all of them) in the background and
+    /// writes ReplayGain tags, with album gain shared by tracks of the same album.
+    #[cfg(feature = "replaygain")]
+    pub fn start_replaygain(&mut self) {
+        if self.replaygain.is_some() || self.files.is_empty() {
+            return;
+        }
+
+        let mut albums: Vec<(bool, Vec<String>)> = Vec::new();
+        let mut album_index: HashMap<String, usize> = HashMap::new();
+        for file in self.batch_files() {
+            let album = Tag::read_from_path(&file)
+                .map(|tag| field_value(&tag, "Album"))
+                .unwrap_or_default();
+            if album.is_empty() {
+                albums.push((false, vec![file]));
+                continue;
+            }
+            match album_index.get(&album) {
+                Some(&index) => albums[index].1.push(file),
+                None => {
+                    album_index.insert(album, albums.len());
+                    albums.push((true, vec![file]));
+                }
+            }
+        }
+
+        self.replaygain = Some(crate::replaygain::spawn(albums));
+        self.message = "ReplayGain: analysing...".to_string();
+    }
+
+    #[cfg(not(feature = "replaygain"))]
+    pub fn start_replaygain(&mut self) {
+        self.message = "✗ Built without ReplayGain support (the replaygain feature)".to_string();
+    }
+
+    pub fn is_busy(&self) -> bool {
+        #[cfg(feature = "replaygain")]
+        if self.replaygain.is_some() {
+            return true;
+        }
+        #[cfg(feature = "waveform")]
+        if self.waveform_job.is_some() {
+            return true;
+        }
+        if self.stats_job.is_some() {
+            return true;
+        }
+        // Ticks while edits wait for the autosave timer
+        self.autosave_interval.is_some() && !self.buffered.is_empty()
+    }
+
+    /// Picks up whatever background work has reported since the last call.
+    pub fn poll_background(&mut self) {
+        #[cfg(feature = "replaygain")]
+        self.poll_replaygain();
+        #[cfg(feature = "waveform")]
+        self.poll_waveform();
+        self.poll_stats();
+        if self.autosave_due() {
+            self.autosave();
+        }
+    }
+
+    /// The waveform of `file` as `width` bars from 0 to 100, computed in the background
+    /// the first time it is asked for; `None` until it is ready or when it failed.
+    #[cfg(feature = "waveform")]
+    pub fn waveform(&mut self, file: &str, width: usize) -> Option<Vec<u64>> {
+        if let Some(levels) = self.waveforms.get(file) {
+            return levels
+                .as_ref()
+                .map(|levels| crate::waveform::bars(levels, width));
+        }
+        // One file at a time; the next one starts once this one is done
+        if self.waveform_job.is_none() {
+            let receiver = crate::waveform::spawn(file.to_string());
+            self.waveform_job = Some((file.to_string(), receiver));
+        }
+        None
+    }
+
+    #[cfg(not(feature = "waveform"))]
+    pub fn waveform(&mut self, _file: &str, _width: usize) -> Option<Vec<u64>> {
+        None
+    }
+
+    #[cfg(feature = "waveform")]
+    fn poll_waveform(&mut self) {
+        let Some((file, receiver)) = self.waveform_job.take() else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(Ok(levels)) => {
+                self.waveforms.insert(file, Some(levels));
+            }
+            Ok(Err(error)) => {
+                self.log(format!(
+                    "could not draw the waveform of {}: {}",
+                    file, error
+                ));
+                self.waveforms.insert(file, None);
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => self.waveform_job = Some((file, receiver)),
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                self.waveforms.insert(file, None);
+            }
+        }
+    }
+
+    #[cfg(feature = "replaygain")]
+    fn poll_replaygain(&mut self) {
+        use crate::replaygain::{write_gain, Progress};
+
+        let Some(receiver) = self.replaygain.take() else {
+            return;
+        };
+        let mut finished = None;
+        let mut disconnected = false;
+        loop {
+            match receiver.try_recv() {
+                Ok(Progress::Analyzed { done, total }) => {
+                    self.message = format!("ReplayGain: analysed {}/{}", done, total);
+                }
+                Ok(Progress::Failed { file, error }) => {
+                    self.log(format!("could not analyse {}: {}", file, error));
+                }
+                Ok(Progress::Finished(gains)) => {
+                    finished = Some(gains);
+                    break;
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+
+        let Some(gains) = finished else {
+            if disconnected {
+                self.message = "✗ ReplayGain analysis stopped unexpectedly".to_string();
+            } else {
+                self.replaygain = Some(receiver);
+            }
+            return;
+        };
+
+        // Written here rather than on the analysis thread so edits never race
+        let mut failed = 0;
+        for gain in &gains {
+            match write_gain(gain) {
+                Ok(_) => {
+                    self.invalidate_file(&gain.file);
+                    self.record(
+                        &gain.file,
+                        format!("set ReplayGain {:.2} dB", gain.track_gain),
+                    );
+                }
+                Err(_) => failed += 1,
+            }
+        }
+        self.message = if failed == 0 {
+            format!("✓ Wrote ReplayGain to {} files", gains.len())
+        } else {
+            format!(
+                "✗ Wrote ReplayGain to {} files, {} failed",
+                gains.len() - failed,
+                failed
+            )
+        };
+    }
+
+    /// Lists the files with embedded art and asks before stripping it from all of them.
+    pub fn preview_remove_all_art(&mut self) {
+        let affected: Vec<(String, u64)> = self
+            .batch_files()
+            .into_iter()
+            .filter_map(|file| {
+                let tag = Tag::read_from_path(&file).ok()?;
+                let bytes: u64 = tag
+                    .pictures()
+                    .map(|picture| picture.data.len() as u64)
+                    .sum();
+                (bytes > 0).then_some((file, bytes))
+            })
+            .collect();
+
+        if affected.is_empty() {
+            self.message = "No embedded art to remove".to_string();
+            return;
+        }
+        self.note_unread_mtimes(affected.iter().map(|(file, _)| file));
+        self.request_confirmation(PendingAction::RemoveArt { affected });
+    }
+
+    /// Asks to swap the Artist and Title of the selected file, a common import mistake.
+    pub fn preview_swap_artist_title(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        let file = self.current_file.clone();
+        let tag = match Tag::read_from_path(&file) {
+            Ok(tag) => tag,
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+                return;
+            }
+        };
+
+        let artist = field_value(&tag, "Artist");
+        let title = field_value(&tag, "Song Name");
+        if artist == title {
+            self.message = "Artist and Title are the same, nothing to swap".to_string();
+            return;
+        }
+        self.note_unread_mtimes([&file]);
+        self.request_confirmation(PendingAction::SwapArtistTitle {
+            file,
+            artist,
+            title,
+        });
+    }
+
+    /// Holds `action` for confirmation, or applies it right away when confirmations are off.
+    fn request_confirmation(&mut self, action: PendingAction) {
+        // The track totals preview is also where renumbering is switched on, so it is
+        // shown even when writes go ahead unconfirmed
+        let always_shown = matches!(action, PendingAction::TrackTotals { .. });
+        self.pending = Some(action);
+        if self.confirm_writes || always_shown || self.needs_strong_confirmation() {
+            self.mode = Mode::Confirm;
+        } else {
+            self.confirm_pending();
+        }
+    }
+
+    /// Whether the pending action loses data for good, and so takes Y rather than y.
+    pub fn needs_strong_confirmation(&self) -> bool {
+        matches!(self.pending, Some(PendingAction::StripTags { .. }))
+    }
+
+    /// Asks to remove every tag from the selected file.
+    pub fn preview_strip_tags(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        if !is_writable(&self.current_file) {
+            self.message = format!("✗ {} is read-only", self.current_file);
+            return;
+        }
+        self.request_confirmation(PendingAction::StripTags {
+            file: self.current_file.clone(),
+        });
+    }
+
+    pub fn cancel_pending(&mut self) {
+        self.mode = Mode::FileSelection;
+        self.message = "Cancelled".to_string();
+        if let Some(PendingAction::Overwrite { file, field, .. }) = self.pending.take() {
+            if self.table.is_some() {
+                self.mode = Mode::TableEdit;
+            }
+            // What the form shows is now out of date
+            self.form = None;
+            self.invalidate_file(&file);
+            self.message = format!("Reloaded {}, the edit of {} was dropped", file, field);
+        }
+    }
+
+    /// Flips one of the matching options of a pending find-and-replace and refreshes its preview.
+    pub fn toggle_pending_option(&mut self, option: char) {
+        match self.pending.take() {
+            Some(PendingAction::Replace { mut find, .. }) => {
+                match option {
+                    'i' => find.case_insensitive = !find.case_insensitive,
+                    'w' => find.whole_field = !find.whole_field,
+                    _ => {}
+                }
+                self.preview_replace(find);
+            }
+            Some(PendingAction::TrackTotals { renumber, .. }) if option == 'r' => {
+                self.preview_track_totals(!renumber);
+            }
+            pending => self.pending = pending,
+        }
+    }
+
+    /// Exports the tags of the marked files (or all of them) to `path`.
+    fn export_json(&mut self, path: &str) {
+        let path = path.trim();
+        if path.is_empty() {
+            self.message = "✗ No file to export to".to_string();
+            return;
+        }
+        let files = self.batch_files();
+        self.message = match export_json(&files, Path::new(path)) {
+            Ok(0) => format!("✓ Exported the tags of {} files to {}", files.len(), path),
+            Ok(unreadable) => format!(
+                "✓ Exported the tags of {} files to {}, {} unreadable",
+                files.len(),
+                path,
+                unreadable
+            ),
+            Err(e) => format!("✗ Error: {}", e),
+        };
+    }
+
+    /// Works out what importing `path` would change and asks before writing it.
+    fn preview_import_json(&mut self, path: &str) {
+        let path = path.trim();
+        let plan = match plan_import(Path::new(path)) {
+            Ok(plan) => plan,
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+                return;
+            }
+        };
+        if plan.changes.is_empty() {
+            self.message = format!(
+                "Nothing to import from {} ({} files not found, {} values refused)",
+                path,
+                plan.missing.len(),
+                plan.invalid.len()
+            );
+            return;
+        }
+        self.note_unread_mtimes(plan.changes.iter().map(|(file, _)| file));
+        self.request_confirmation(PendingAction::ImportJson {
+            source: path.to_string(),
+            plan,
+        });
+    }
+
+    /// Pairs the marked files (or all of them) with the files of `target_dir` by track
+    /// number and offers to copy their Title, Artist, Album and Date across.
+    fn preview_copy_by_track(&mut self, target_dir: &str) {
+        let target_dir = target_dir.trim().trim_end_matches('/');
+        let scan = get_music_files(target_dir, &self.extensions, false);
+        if scan.files.is_empty()
+            && let Some(warning) = scan.warnings.first()
+        {
+            self.message = format!("✗ Cannot read {}", warning);
+            return;
+        }
+        let targets = scan.files;
+
+        // A track number held by two sources, say on a two-disc album, matches neither
+        let sources = self.batch_files();
+        let mut by_track: HashMap<u32, Vec<(String, Tag)>> = HashMap::new();
+        for source in &sources {
+            if let Ok(tag) = Tag::read_from_path(source)
+                && let Some(track) = tag.track()
+            {
+                by_track
+                    .entry(track)
+                    .or_default()
+                    .push((source.clone(), tag));
+            }
+        }
+        let source_identities: HashSet<PathBuf> = sources
+            .iter()
+            .filter_map(|source| fs::canonicalize(source).ok())
+            .collect();
+
+        let mut matches = Vec::new();
+        let mut unmatched = Vec::new();
+        for name in targets {
+            let target = Path::new(target_dir).join(&name).display().to_string();
+            if fs::canonicalize(&target).is_ok_and(|path| source_identities.contains(&path)) {
+                continue;
+            }
+            let tag = match read_tag_for_write(&target) {
+                Ok(tag) => tag,
+                Err(e) => {
+                    unmatched.push((target, e.to_string()));
+                    continue;
+                }
+            };
+            let Some(track) = tag.track() else {
+                unmatched.push((target, "no track number".to_string()));
+                continue;
+            };
+            let source = match by_track.get(&track).map(Vec::as_slice) {
+                Some([source]) => source,
+                Some(_) => {
+                    unmatched.push((target, format!("several sources are track {}", track)));
+                    continue;
+                }
+                None => {
+                    unmatched.push((target, format!("no source is track {}", track)));
+                    continue;
+                }
+            };
+
+            let edits: Vec<(String, String, String)> = COPY_BY_TRACK_FIELDS
+                .iter()
+                .filter_map(|field| {
+                    let old = field_value(&tag, field);
+                    let new = field_value(&source.1, field);
+                    (!new.is_empty() && new != old).then(|| (field.to_string(), old, new))
+                })
+                .collect();
+            if !edits.is_empty() {
+                matches.push(TrackMatch {
+                    source: source.0.clone(),
+                    target,
+                    edits,
+                });
+            }
+        }
+
+        if matches.is_empty() {
+            self.message = format!(
+                "Nothing to copy to {} ({} files without a match)",
+                target_dir,
+                unmatched.len()
+            );
+            return;
+        }
+        self.note_unread_mtimes(matches.iter().map(|track_match| &track_match.target));
+        self.request_confirmation(PendingAction::CopyByTrack {
+            target_dir: target_dir.to_string(),
+            matches,
+            unmatched,
+        });
+    }
+
+    /// Offers to give every file in the selected file's folder the folder's file count
+    /// as its track total, and optionally to renumber them in list order.
+    pub fn preview_track_totals(&mut self, renumber: bool) {
+        let Some(current) = self.files.get(self.selected_file) else {
+            return;
+        };
+        let folder = Path::new(current).parent().map(Path::to_path_buf);
+        let album: Vec<String> = self
+            .files
+            .iter()
+            .filter(|file| Path::new(file).parent().map(Path::to_path_buf) == folder)
+            .cloned()
+            .collect();
+        let total = album.len();
+
+        let changes: Vec<(String, String, Option<String>)> = album
+            .into_iter()
+            .enumerate()
+            .map(|(index, file)| {
+                let tag = Tag::read_from_path(&file).ok();
+                let old = tag
+                    .as_ref()
+                    .map(|tag| field_value(tag, "Track"))
+                    .unwrap_or_default();
+                let number = if renumber {
+                    Some(index as u32 + 1)
+                } else {
+                    tag.as_ref().and_then(|tag| tag.track())
+                };
+                let new = number.map(|number| format!("{}/{}", number, total));
+                (file, old, new)
+            })
+            .filter(|(_, old, new)| new.as_ref() != Some(old))
+            .collect();
+
+        if changes.is_empty() {
+            self.message = format!("Every file already has a total of {}", total);
+            return;
+        }
+        let folder = match folder {
+            Some(folder) if !folder.as_os_str().is_empty() => folder.display().to_string(),
+            _ => self.location.clone(),
+        };
+        self.note_unread_mtimes(changes.iter().map(|(file, _, _)| file));
+        self.request_confirmation(PendingAction::TrackTotals {
+            folder,
+            renumber,
+            changes,
+        });
+    }
+
+    /// Moves everything known about `old` over to its new name `target`.
+    fn track_rename(&mut self, old: &str, target: &str) {
+        if let Some(entry) = self.files.iter_mut().find(|file| *file == old) {
+            *entry = target.to_string();
+        }
+        for edit in self.buffered.iter_mut().filter(|edit| edit.file == old) {
+            edit.file = target.to_string();
+        }
+        self.buffered.sort_by(|a, b| a.file.cmp(&b.file));
+        if self.current_file == old {
+            self.current_file = target.to_string();
+        }
+        if let Some(info) = self.file_info.remove(old) {
+            self.file_info.insert(target.to_string(), info);
+        }
+        if let Some(tags) = self.tag_cache.remove(old) {
+            self.tag_cache.insert(target.to_string(), tags);
+        }
+        if let Some(modified) = self.read_mtimes.remove(old) {
+            self.read_mtimes.insert(target.to_string(), modified);
+        }
+        if let Some(tags) = self.original_tags.remove(old) {
+            self.original_tags.insert(target.to_string(), tags);
+        }
+        if let Some(error) = self.read_errors.remove(old) {
+            self.read_errors.insert(target.to_string(), error);
+        }
+        if self.selected_files.remove(old) {
+            self.selected_files.insert(target.to_string());
+        }
+        if let Some(history) = self.history.remove(old) {
+            self.history.insert(target.to_string(), history);
+        }
+        if let Some(art) = self
+            .album_art_cache
+            .remove(&ArtKey::Embedded(old.to_string()))
+        {
+            self.album_art_cache
+                .insert(ArtKey::Embedded(target.to_string()), art);
+        }
+    }
+
+    /// Moves the cursor to `file`, as given on the command line.
+    pub fn select_file(&mut self, file: &str) {
+        match self.files.iter().position(|listed| listed == file) {
+            Some(index) => {
+                self.selected_file = index;
+                self.current_file = file.to_string();
+            }
+            None => self.message = format!("✗ {} is not a listed music file", file),
+        }
+    }
+
+    /// Puts the files back in order, by path or by album, keeping the current one selected.
+    fn sort_files(&mut self) {
+        if self.sort_by_album {
+            let files = std::mem::take(&mut self.files);
+            let mut keyed: Vec<_> = files
+                .into_iter()
+                .map(|file| {
+                    let (album, track) = self
+                        .tags_for_file(&file)
+                        .map(|tags| (tags.album.to_lowercase(), tags.track))
+                        .unwrap_or_default();
+                    // Files without an album go last
+                    ((album.is_empty(), album, track), file)
+                })
+                .collect();
+            keyed.sort();
+            self.files = keyed.into_iter().map(|(_, file)| file).collect();
+        } else {
+            self.files.sort();
+        }
+        self.reselect_current_file();
+    }
+
+    /// Switches the files list between path order and album order.
+    pub fn toggle_album_sort(&mut self) {
+        self.sort_by_album = !self.sort_by_album;
+        self.sort_files();
+        self.message = if self.sort_by_album {
+            "Sorted by album".to_string()
+        } else {
+            "Sorted by path".to_string()
+        };
+    }
+
+    /// The heading to show above file `index` when sorted by album: its album, if the
+    /// file before it is on another one.
+    pub fn album_heading(&mut self, index: usize) -> Option<String> {
+        if !self.sort_by_album {
+            return None;
+        }
+        let album = |app: &mut App, index: usize| {
+            let file = app.files[index].clone();
+            app.tags_for_file(&file)
+                .map(|tags| tags.album)
+                .unwrap_or_default()
+        };
+        let current = album(self, index);
+        if index > 0 && album(self, index - 1).eq_ignore_ascii_case(&current) {
+            return None;
+        }
+        Some(if current.is_empty() {
+            "Unknown Album".to_string()
+        } else {
+            current
+        })
+    }
+
+    /// Puts the cursor back on `current_file` after the list was reordered.
+    fn reselect_current_file(&mut self) {
+        self.selected_file = self
+            .files
+            .iter()
+            .position(|file| *file == self.current_file)
+            .unwrap_or(0);
+    }
+
+    /// Works out the new name of every file for `template` and shows them for review,
+    /// flagging names that are missing, illegal or would collide.
+    fn preview_rename_template(&mut self, template: &str) {
+        let template = template.trim();
+        if template.is_empty() {
+            self.message = "✗ No template given".to_string();
+            return;
+        }
+
+        let mut renames: Vec<(String, String, Option<String>)> = self
+            .batch_files()
+            .into_iter()
+            .map(|file| {
+                let target = Tag::read_from_path(&file)
+                    .map_err(Box::<dyn Error>::from)
+                    .and_then(|tag| {
+                        rename_from_template(&file, &tag, template, &self.filename_rules)
+                    });
+                match target {
+                    Ok(target) => (file, target, None),
+                    Err(e) => (file.clone(), file, Some(e.to_string())),
+                }
+            })
+            .collect();
+
+        flag_rename_collisions(&mut renames);
+        renames.retain(|(old, target, problem)| problem.is_some() || old != target);
+
+        if renames.is_empty() {
+            self.message = "All files already match the template".to_string();
+            return;
+        }
+        self.confirm_scroll = 0;
+        self.request_confirmation(PendingAction::RenameAll {
+            renames,
+            tag_changes: HashMap::new(),
+        });
+    }
+
+    /// Fills the title, artist and track missing from each tag with what the file name
+    /// says, then works out the name `template` gives the result. Both are shown at once
+    /// for review.
+    fn preview_normalize(&mut self, template: &str) {
+        let template = template.trim();
+        if template.is_empty() {
+            self.message = "✗ No template given".to_string();
+            return;
+        }
+
+        let mut tag_changes = HashMap::new();
+        let mut renames: Vec<(String, String, Option<String>)> = Vec::new();
+        for file in self.batch_files() {
+            let mut tag = match read_tag_for_write(&file) {
+                Ok(tag) => tag,
+                Err(e) => {
+                    renames.push((file.clone(), file, Some(e.to_string())));
+                    continue;
+                }
+            };
+
+            let parsed = parse_filename(&file);
+            let mut changes = Vec::new();
+            for (field, value) in [
+                ("Song Name", parsed.title),
+                ("Artist", parsed.artist),
+                ("Track", parsed.track.map(|track| track.to_string())),
+            ] {
+                let Some(value) = value else {
+                    continue;
+                };
+                let previous = field_value(&tag, field);
+                if !previous.trim().is_empty() {
+                    continue;
+                }
+                match field {
+                    "Song Name" => tag.set_title(value.as_str()),
+                    "Artist" => tag.set_artist(value.as_str()),
+                    _ => tag.set_track(parsed.track.unwrap_or_default()),
+                }
+                changes.push((field.to_string(), previous, value));
+            }
+
+            match rename_from_template(&file, &tag, template, &self.filename_rules) {
+                Ok(target) => renames.push((file.clone(), target, None)),
+                Err(e) => renames.push((file.clone(), file.clone(), Some(e.to_string()))),
+            }
+            if !changes.is_empty() {
+                tag_changes.insert(file, changes);
+            }
+        }
+
+        flag_rename_collisions(&mut renames);
+        renames.retain(|(old, target, problem)| {
+            problem.is_some() || old != target || tag_changes.contains_key(old)
+        });
+
+        if renames.is_empty() {
+            self.message = "All files already match the template".to_string();
+            return;
+        }
+        self.confirm_scroll = 0;
+        self.note_unread_mtimes(tag_changes.keys());
+        self.request_confirmation(PendingAction::RenameAll {
+            renames,
+            tag_changes,
+        });
+    }
+
+    pub fn scroll_confirm(&mut self, scroll: Scroll) {
+        let len = match &self.pending {
+            Some(PendingAction::RenameAll { renames, .. }) => renames.len(),
+            _ => 0,
+        };
+        self.confirm_scroll = scroll.apply(self.confirm_scroll, len, self.view_height);
+    }
+
+    pub fn confirm_scroll(&self) -> usize {
+        self.confirm_scroll
+    }
+
+    /// Whether `file` was only found by probing its content, its extension being
+    /// none of the configured ones.
+    pub fn is_mislabeled(&self, file: &str) -> bool {
+        !has_extension(Path::new(file), &self.extensions)
+    }
+
+    /// Renames a probed file so it ends in `.mp3`, which is what its content is.
+    pub fn fix_extension(&mut self) {
+        let file = match self.files.get(self.selected_file) {
+            Some(file) if self.is_mislabeled(file) => file.clone(),
+            _ => {
+                self.message = "The extension already matches".to_string();
+                return;
+            }
+        };
+        let Some(stem) = Path::new(&file).file_stem().and_then(|stem| stem.to_str()) else {
+            return;
+        };
+        let new_name = format!("{}.mp3", stem);
+        self.current_file = file;
+        self.rename_current_file(&new_name);
+    }
+
+    fn rename_current_file(&mut self, new_name: &str) {
+        let old = self.current_file.clone();
+        let target = match renamed_path(&old, new_name) {
+            Ok(target) => target,
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+                return;
+            }
+        };
+
+        if target == old {
+            return;
+        }
+        // Files, caches and the log are all keyed by these names, so they must stay unique
+        if self.files.contains(&target) {
+            self.message = format!("✗ Cannot rename: {} is already in the list", target);
+            return;
+        }
+        // fs::rename replaces whatever is there, listed or not
+        if rename_would_clobber(&old, &target) {
+            self.message = format!("✗ Cannot rename: {} already exists", target);
+            return;
+        }
+
+        if let Err(e) = fs::rename(&old, &target) {
+            self.message = format!("✗ Error: {}", e);
+            return;
+        }
+
+        self.track_rename(&old, &target);
+        self.sort_files();
+
+        self.message = format!("✓ Renamed {} to {} (z to undo)", old, target);
+        self.record(&target, format!("renamed from {}", old));
+        self.undo_stack.push(Undo::Rename(vec![(old, target)]));
+    }
+
+    /// Reverses the last operation on the undo stack. Renames are only undone when none
+    /// of the old names has been taken again in the meantime.
+    pub fn undo(&mut self) {
+        let Some(last) = self.undo_stack.pop() else {
+            self.message = "Nothing to undo".to_string();
+            return;
+        };
+
+        match last {
+            Undo::Rename(renames) => {
+                let taken: Vec<&str> = renames
+                    .iter()
+                    .filter(|(old, new)| self.files.contains(old) || rename_would_clobber(new, old))
+                    .map(|(old, _)| old.as_str())
+                    .collect();
+                if let Some(first) = taken.first() {
+                    self.message = format!(
+                        "✗ Cannot undo the rename: {} exists again{}",
+                        first,
+                        match taken.len() {
+                            1 => String::new(),
+                            count => format!(" (and {} more)", count - 1),
+                        }
+                    );
+                    self.undo_stack.push(Undo::Rename(renames));
+                    return;
+                }
+
+                let mut restored = 0;
+                let mut failed = 0;
+                for (old, new) in renames.iter().rev() {
+                    match fs::rename(new, old) {
+                        Ok(_) => {
+                            restored += 1;
+                            self.track_rename(new, old);
+                            self.record(old, format!("renamed back from {}", new));
+                        }
+                        Err(e) => {
+                            failed += 1;
+                            self.log(format!("could not rename {} back: {}", new, e));
+                        }
+                    }
+                }
+                self.sort_files();
+                self.message = match (restored, failed) {
+                    (1, 0) => format!("✓ Renamed {} back to {}", renames[0].1, renames[0].0),
+                    (_, 0) => format!("✓ Undid the rename of {} files", restored),
+                    _ => format!(
+                        "✗ Undid the rename of {} files, {} failed (see the log)",
+                        restored, failed
+                    ),
+                };
+            }
+        }
+    }
+
+    /// Keeps the embedded cover of the selected file for pasting elsewhere.
+    pub fn yank_art(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        match self.extract_album_art_bytes(&self.current_file) {
+            Ok(Some(art)) => {
+                self.message = format!(
+                    "Yanked the cover of {} ({}), p to paste",
+                    self.current_file,
+                    format_size(art.0.len() as u64)
+                );
+                self.yanked_art = Some(art);
+            }
+            Ok(None) => self.message = "No embedded art to yank".to_string(),
+            Err(e) => self.message = format!("✗ Read error: {}", e),
+        }
+    }
+
+    /// Embeds the yanked cover into the marked files, or the selected one.
+    pub fn paste_art(&mut self) {
+        let Some((data, mime_type)) = self.yanked_art.clone() else {
+            self.message = "Nothing yanked yet: Y on a file with art first".to_string();
+            return;
+        };
+        let files = if self.selected_files.is_empty() {
+            self.files
+                .get(self.selected_file)
+                .cloned()
+                .into_iter()
+                .collect()
+        } else {
+            self.batch_files()
+        };
+
+        let mut outcome = BatchOutcome::default();
+        for file in files {
+            let expected = self.read_mtimes.get(&file).copied();
+            let result = embed_art(&file, data.clone(), &mime_type, expected);
+            if self.count_write(&mut outcome, &file, result) {
+                outcome.done += 1;
+                self.record(&file, "pasted the yanked cover".to_string());
+            }
+        }
+        self.message = outcome.message(format!("Pasted the cover into {} files", outcome.done));
+    }
+
+    fn embed_art_from_path(&mut self, path: &str) {
+        let path = PathBuf::from(path);
+        let file = self.current_file.clone();
+        let expected = self.read_mtimes.get(&file).copied();
+        let result = fs::read(&path)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|data| {
+                // Refuse anything that would not display as a cover later on
+                image::guess_format(&data)?;
+                embed_art(&file, data, mime_for_image(&path), expected)
+            });
+        self.invalidate_file(&file);
+        match result {
+            Ok(_) => {
+                self.message = format!("✓ Embedded {} into {}", path.display(), self.current_file);
+                self.record(&file, format!("embedded {}", path.display()));
+            }
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+            }
+        }
+    }
+
+    /// Records a successful write in the session log.
+    fn log(&mut self, entry: String) {
+        self.log
+            .push(format!("{} {}", Local::now().format("%H:%M"), entry));
+    }
+
+    /// Records a change written to `file`, in the session log and the file's history.
+    fn record(&mut self, file: &str, change: String) {
+        self.log(format!("{}: {}", file, change));
+        self.history
+            .entry(file.to_string())
+            .or_default()
+            .push(ChangeRecord {
+                time: Local::now().format("%H:%M").to_string(),
+                change,
+            });
+    }
+
+    pub fn open_history(&mut self) {
+        let Some(file) = self.files.get(self.selected_file) else {
+            return;
+        };
+        let changes = self.history.get(file).map_or(0, Vec::len);
+        self.history_scroll = changes.saturating_sub(1);
+        self.mode = Mode::History;
+    }
+
+    pub fn close_history(&mut self) {
+        self.mode = Mode::FileSelection;
+    }
+
+    pub fn scroll_history(&mut self, scroll: Scroll) {
+        let changes = self.history().map_or(0, |(_, changes)| changes.len());
+        self.history_scroll = scroll.apply(self.history_scroll, changes, self.view_height);
+    }
+
+    /// The selected file and every change written to it this session.
+    pub fn history(&self) -> Option<(&str, &[ChangeRecord])> {
+        let file = self.files.get(self.selected_file)?;
+        let changes = self.history.get(file).map_or(&[][..], Vec::as_slice);
+        Some((file, changes))
+    }
+
+    pub fn history_scroll(&self) -> usize {
+        self.history_scroll
+    }
+
+    pub fn open_log(&mut self) {
+        self.mode = Mode::Log;
+        // Start at the most recent entries
+        self.log_scroll = self.log.len().saturating_sub(1);
+    }
+
+    /// Sums up the whole list, reading every tag not cached yet. The playing time needs
+    /// every file read through, so it is measured in the background.
+    pub fn open_stats(&mut self) {
+        let files = self.files.clone();
+        let tags: Vec<Option<TagInfo>> =
+            files.iter().map(|file| self.tags_for_file(file)).collect();
+        self.stats = Some(compute_stats(&files, &tags));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(total_duration(&files));
+        });
+        self.stats_job = Some(receiver);
+        self.mode = Mode::Stats;
+    }
+
+    fn poll_stats(&mut self) {
+        let Some(receiver) = self.stats_job.take() else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok((duration, unknown)) => {
+                if let Some(stats) = &mut self.stats {
+                    stats.duration = Some(duration);
+                    stats.unknown_duration = unknown;
+                }
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => self.stats_job = Some(receiver),
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {}
+        }
+    }
+
+    /// Checks the whole list for common problems, reading every tag not cached yet.
+    pub fn open_verify(&mut self) {
+        let files = self.files.clone();
+        let tags: Vec<Option<TagInfo>> =
+            files.iter().map(|file| self.tags_for_file(file)).collect();
+        let issues = verify_library(&files, &tags);
+        self.message = match issues.len() {
+            0 => format!("✓ No problems found in {} files", files.len()),
+            count => format!("{} problems found in {} files", count, files.len()),
+        };
+        self.issues = Some(issues);
+        self.issues_scroll = 0;
+        self.mode = Mode::Verify;
+    }
+
+    pub fn close_verify(&mut self) {
+        self.issues = None;
+        self.mode = Mode::FileSelection;
+    }
+
+    pub fn scroll_verify(&mut self, scroll: Scroll) {
+        let len = self.issues.as_ref().map_or(0, Vec::len);
+        self.issues_scroll = scroll.apply(self.issues_scroll, len, self.view_height);
+    }
+
+    pub fn issues(&self) -> Option<&[Issue]> {
+        self.issues.as_deref()
+    }
+
+    pub fn issues_scroll(&self) -> usize {
+        self.issues_scroll
+    }
+
+    pub fn close_stats(&mut self) {
+        self.stats = None;
+        self.stats_job = None;
+        self.mode = Mode::FileSelection;
+    }
+
+    pub fn stats(&self) -> Option<&LibraryStats> {
+        self.stats.as_ref()
+    }
+
+    pub fn close_log(&mut self) {
+        self.mode = Mode::FileSelection;
+    }
+
+    pub fn scroll_log(&mut self, scroll: Scroll) {
+        self.log_scroll = scroll.apply(self.log_scroll, self.log.len(), self.view_height);
+    }
+
+    pub fn set_view_height(&mut self, height: usize) {
+        self.view_height = height;
+    }
+
+    /// Drops everything cached about `filename` after the app has written to it.
+    fn invalidate_file(&mut self, filename: &str) {
+        self.album_art_cache
+            .remove(&ArtKey::Embedded(filename.to_string()));
+        self.tag_cache.remove(filename);
+        self.read_errors.remove(filename);
+        self.note_mtime(filename);
+        self.file_info
+            .insert(filename.to_string(), FileInfo::read(filename));
+    }
+
+    /// Remembers the modification time `filename` has now, as the one its tag was read at.
+    fn note_mtime(&mut self, filename: &str) {
+        match modified_time(filename) {
+            Some(modified) => self.read_mtimes.insert(filename.to_string(), modified),
+            None => self.read_mtimes.remove(filename),
+        };
+    }
+
+    /// Remembers when each of `files` was last modified, unless its tag was read before,
+    /// so that a batch previewed from them leaves alone any another program changes first.
+    fn note_unread_mtimes<'a>(&mut self, files: impl IntoIterator<Item = &'a String>) {
+        for file in files {
+            if !self.read_mtimes.contains_key(file) {
+                self.note_mtime(file);
+            }
+        }
+    }
+
+    /// The system clipboard, opened on first use.
+    fn clipboard(&mut self) -> Option<&mut Clipboard> {
+        // The clipboard is kept open for the whole session: on X11 the copied
+        // contents are served by this process and vanish once it is dropped
+        if self.clipboard.is_none() {
+            match Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(e) => {
+                    self.message = format!("✗ Clipboard unavailable: {}", e);
+                    return None;
+                }
+            }
+        }
+        self.clipboard.as_mut()
+    }
+
+    pub fn copy_to_clipboard(&mut self, text: String) {
+        if let Some(clipboard) = self.clipboard() {
+            let message = match clipboard.set_text(text) {
+                Ok(_) => "✓ Copied tags to clipboard".to_string(),
+                Err(e) => format!("✗ Error: {}", e),
+            };
+            self.message = message;
+        }
+    }
+
+    /// Embeds the image on the clipboard, say one copied from a browser, as the
+    /// selected file's cover.
+    pub fn paste_clipboard_art(&mut self) {
+        let Some(file) = self.files.get(self.selected_file).cloned() else {
+            return;
+        };
+        let Some(clipboard) = self.clipboard() else {
+            return;
+        };
+        let image = match clipboard.get_image() {
+            Ok(image) => image,
+            Err(arboard::Error::ContentNotAvailable) => {
+                self.message = "No image on the clipboard".to_string();
+                return;
+            }
+            Err(e) => {
+                self.message = format!("✗ Error: {}", e);
+                return;
+            }
+        };
+
+        let result = encode_png(
+            image.width as u32,
+            image.height as u32,
+            image.bytes.into_owned(),
+        )
+        .and_then(|png| {
+            let expected = self.read_mtimes.get(&file).copied();
+            embed_art(&file, png.data, "image/png", expected).map(|_| (png.width, png.height))
+        });
+        self.invalidate_file(&file);
+        match result {
+            Ok((width, height)) => {
+                let scaled = if (width as usize, height as usize) == (image.width, image.height) {
+                    String::new()
+                } else {
+                    format!(", scaled from {}×{}", image.width, image.height)
+                };
+                self.message = format!(
+                    "✓ Embedded the clipboard image ({}×{}{}) into {}",
+                    width, height, scaled, file
+                );
+                self.record(&file, "embedded the clipboard image".to_string());
+            }
+            Err(e) => self.message = format!("✗ Error: {}", e),
+        }
+    }
+
+    pub fn open_gallery(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        self.gallery_selected = self.selected_file;
+        self.mode = Mode::Gallery;
+    }
+
+    pub fn close_gallery(&mut self) {
+        self.mode = Mode::FileSelection;
+    }
+
+    /// Jumps to the file of the selected cover.
+    pub fn choose_gallery_file(&mut self) {
+        if let Some(file) = self.files.get(self.gallery_selected).cloned() {
+            self.select_file(&file);
+        }
+        self.mode = Mode::FileSelection;
+    }
+
+    /// Moves the gallery selection by `columns` covers and `rows` rows, stopping at
+    /// either end.
+    pub fn move_gallery(&mut self, columns: isize, rows: isize) {
+        let step = columns + rows * self.gallery_width as isize;
+        let last = self.files.len().saturating_sub(1);
+        self.gallery_selected = self.gallery_selected.saturating_add_signed(step).min(last);
+    }
+
+    /// How many covers fit in a row `width` cells wide.
+    pub fn gallery_columns(&self, width: u16) -> usize {
+        let columns = self
+            .gallery_columns
+            .unwrap_or(width / GALLERY_CELL_WIDTH)
+            .clamp(1, width.max(1));
+        columns as usize
+    }
+
+    pub fn set_gallery_size(&mut self, columns: usize, rows: usize) {
+        self.gallery_width = columns.max(1);
+        self.gallery_cells = self.gallery_width * rows;
+    }
+
+    pub fn gallery_selected(&self) -> usize {
+        self.gallery_selected
+    }
+
+    pub fn toggle_art(&mut self) {
+        self.show_art = !self.show_art;
+    }
+
+    pub fn sort_by_album(&self) -> bool {
+        self.sort_by_album
+    }
+
+    pub fn toggle_path_display(&mut self) {
+        self.path_display = match self.path_display {
+            PathDisplay::Full => PathDisplay::Basename,
+            PathDisplay::Basename => PathDisplay::Full,
+        };
+    }
+
+    pub fn path_display(&self) -> PathDisplay {
+        self.path_display
+    }
+
+    pub fn art_placeholder(&self) -> &[String] {
+        &self.art_placeholder
+    }
+
+    pub fn toggle_monochrome(&mut self) {
+        self.monochrome = !self.monochrome;
+    }
+
+    pub fn monochrome(&self) -> bool {
+        self.monochrome
+    }
+
+    /// Widens (or with a negative step narrows) the files list.
+    pub fn nudge_files_width(&mut self, step: i16) {
+        let width = self.files_width.saturating_add_signed(step);
+        if SPLIT_RANGE.contains(&width) {
+            self.files_width = width;
+        }
+    }
+
+    /// Adjusts the assumed cell height, for when album art looks squashed or stretched.
+    pub fn nudge_font_height(&mut self, delta: i16) {
+        let Some(image_picker) = self.image_picker.as_ref() else {
+            self.message = "Image support unavailable".to_string();
+            return;
+        };
+        let (width, height) = image_picker.font_size();
+        let height = height.saturating_add_signed(delta).max(1);
+
+        let mut picker = Picker::from_fontsize((width, height));
+        picker.set_protocol_type(image_picker.protocol_type());
+        self.image_picker = Some(picker);
+
+        // Every cached protocol was encoded for the old cell size
+        self.album_art_cache.clear();
+        self.art_cache_order.clear();
+        self.message = format!("Album art cell size: {}×{}px", width, height);
+    }
+
+    /// Moves on to the next resampling filter, for art that looks too blocky or blurred.
+    pub fn cycle_art_filter(&mut self) {
+        if self.image_picker.is_none() {
+            self.message = "Image support unavailable".to_string();
+            return;
+        }
+        self.art_filter = (self.art_filter + 1) % ART_FILTERS.len();
+
+        // Cached protocols keep the image as resampled with the old filter
+        self.album_art_cache.clear();
+        self.art_cache_order.clear();
+        self.message = format!("Album art filter: {}", ART_FILTERS[self.art_filter].0);
+    }
+
+    pub fn art_filter(&self) -> FilterType {
+        ART_FILTERS[self.art_filter].1
+    }
+
+    pub fn back_to_files(&mut self) {
+        self.mode = Mode::FileSelection;
+        self.message = "Select a file to edit".to_string();
+    }
+
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn is_file_selected(&self, file: &str) -> bool {
+        self.selected_files.contains(file)
+    }
+
+    pub fn selected_count(&self) -> usize {
+        self.selected_files.len()
+    }
+
+    pub fn file_info(&self, filename: &str) -> Option<&FileInfo> {
+        self.file_info.get(filename)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.file_info.values().map(|info| info.size).sum()
+    }
+
+    pub fn selected_file(&self) -> usize {
+        self.selected_file
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    pub fn quick_track(&self) -> bool {
+        self.quick_track
+    }
+
+    pub fn files_width(&self) -> u16 {
+        self.files_width
+    }
+
+    pub fn preview_width(&self) -> u16 {
+        self.preview_width
+    }
+
+    pub fn art_enabled(&self) -> bool {
+        self.image_picker.is_some()
+    }
+
+    pub fn show_art(&self) -> bool {
+        self.show_art
+    }
+
+    pub fn advanced_fields(&self) -> bool {
+        self.advanced_fields
+    }
+
+    pub fn selected_field(&self) -> usize {
+        self.selected_field
+    }
+
+    pub fn input_buffer(&self) -> &str {
+        &self.input_buffer
+    }
+
+    pub fn current_field(&self) -> Option<&String> {
+        self.current_field.as_ref()
+    }
+
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    pub fn prompt(&self) -> Option<PromptKind> {
+        self.prompt
+    }
+
+    pub fn log_entries(&self) -> &[String] {
+        &self.log
+    }
+
+    pub fn log_scroll(&self) -> usize {
+        self.log_scroll
+    }
+
+    pub fn pending(&self) -> Option<&PendingAction> {
+        self.pending.as_ref()
+    }
+
+    pub fn completions(&self) -> &[String] {
+        &self.completions
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+
+    pub fn push_to_buffer(&mut self, c: char) {
+        // Structured fields only take the characters their format can contain
+        if self.mode == Mode::Editing
+            && let Some(input) = self.current_field.as_deref().and_then(field_input)
+            && !(input.accepts)(c)
+        {
+            return;
+        }
+        self.input_buffer.push(c);
+        self.completions.clear();
+        self.discard_requested = false;
+    }
+
+    pub fn pop_from_buffer(&mut self) {
+        self.input_buffer.pop();
+        self.completions.clear();
+        self.discard_requested = false;
+    }
+
+    /// The tags of a file, read once and then served from the cache until it is written.
+    pub fn tags_for_file(&mut self, filename: &str) -> Option<TagInfo> {
+        if let Some(cached) = self.tag_cache.get(filename) {
+            return cached.clone();
+        }
+
+        self.note_mtime(filename);
+        let tag_info = match read_tag_info(filename, &self.preview_fields) {
+            Ok(tag_info) => {
+                self.original_tags
+                    .entry(filename.to_string())
+                    .or_insert_with(|| tag_info.clone());
+                Some(tag_info)
+            }
+            Err(e) => {
+                self.read_errors.insert(filename.to_string(), e);
+                None
+            }
+        };
+        self.tag_cache
+            .insert(filename.to_string(), tag_info.clone());
+        tag_info
+    }
+
+    /// What the preview row for `frame` held when `filename` was first read, if it has
+    /// changed since.
+    pub fn changed_since_load(&self, filename: &str, frame: &str, value: &str) -> Option<&str> {
+        let original = self.original_tags.get(filename)?;
+        let (_, _, was) = original.preview.iter().find(|(_, id, _)| id == frame)?;
+        (was != value).then_some(was.as_str())
+    }
+
+    /// The track of `filename` among the files of its folder tagged with the same album.
+    /// Only the folder is looked at, so a big library is not read in full to show it.
+    pub fn album_position(&mut self, filename: &str) -> Option<AlbumPosition> {
+        let tag_info = self.tags_for_file(filename)?;
+        let track = tag_info.track?;
+        if tag_info.album.trim().is_empty() {
+            return None;
+        }
+
+        let folder = Path::new(filename).parent().map(Path::to_path_buf);
+        let neighbours: Vec<String> = self
+            .files
+            .iter()
+            .filter(|file| Path::new(file).parent().map(Path::to_path_buf) == folder)
+            .cloned()
+            .collect();
+        let mut tracks = HashSet::new();
+        for file in neighbours {
+            if let Some(other) = self.tags_for_file(&file)
+                && other
+                    .album
+                    .trim()
+                    .eq_ignore_ascii_case(tag_info.album.trim())
+                && let Some(track) = other.track
+            {
+                tracks.insert(track);
+            }
+        }
+
+        let highest = tracks.iter().copied().max().unwrap_or(track);
+        let count = tag_info
+            .total_tracks
+            .filter(|total| *total <= MAX_ALBUM_TRACKS)
+            .unwrap_or(0)
+            .max(highest.min(MAX_ALBUM_TRACKS));
+        Some(AlbumPosition {
+            track,
+            count,
+            album: tag_info.album,
+            tracks,
+        })
+    }
+
+    /// Why the tag of `filename` could not be read, once `tags_for_file` has failed on it.
+    pub fn read_error(&self, filename: &str) -> Option<&str> {
+        self.read_errors.get(filename).map(String::as_str)
+    }
+}
+
+/// Flags the renames that would lose a file: two files aiming for one name, or a name
+/// that is already taken.
+fn flag_rename_collisions(renames: &mut [(String, String, Option<String>)]) {
+    let mut targets: HashMap<String, usize> = HashMap::new();
+    for (_, target, problem) in renames.iter() {
+        if problem.is_none() {
+            *targets.entry(target.clone()).or_default() += 1;
+        }
+    }
+    for (old, target, problem) in renames.iter_mut() {
+        if problem.is_some() || old == target {
+            continue;
+        }
+        if targets[target.as_str()] > 1 {
+            *problem = Some("same name as another file".to_string());
+        } else if rename_would_clobber(old, target) {
+            *problem = Some("already exists".to_string());
+        }
+    }
+}
+
+fn field_names(advanced: bool) -> Vec<String> {
+    let mut fields: Vec<String> = BASIC_FIELDS.iter().map(|name| name.to_string()).collect();
+    if advanced {
+        fields.extend(ADVANCED_FIELDS.iter().map(|name| name.to_string()));
+        fields.extend(
+            ADVANCED_TEXT_FIELDS
+                .iter()
+                .map(|(name, _)| name.to_string()),
+        );
+    }
+    fields
+}
+
+/// Embedded pictures above this size are only kept as a thumbnail for display.
+const LARGE_ART_BYTES: usize = 2 * 1024 * 1024;
+const ART_THUMBNAIL_SIZE: u32 = 1024;
+/// Widest and tallest a large picture may be to get decoded at all, which bounds the
+/// memory decoding takes.
+const LARGE_ART_MAX_SIDE: u32 = 4096;
+/// Width of a gallery cover when the columns are fitted to the screen.
+const GALLERY_CELL_WIDTH: u16 = 22;
+/// Decoded covers kept at most, unless more of the gallery is on screen.
+const ART_CACHE_LIMIT: usize = 64;
+
+/// `JPEG` for `image/jpeg`, and so on.
+fn image_format_name(mime_type: &str) -> String {
+    match mime_type.rsplit('/').next().unwrap_or(mime_type) {
+        "jpg" | "jpeg" => "JPEG".to_string(),
+        "" => "unknown".to_string(),
+        subtype => subtype.to_uppercase(),
+    }
+}
+
+/// Decodes a cover, with its size as stored. Large pictures are decoded within limits
+/// and swapped for a thumbnail: the protocol keeps its source image around and
+/// re-scales it on every resize, and a thumbnail is still plenty for a terminal. A
+/// picture past the limits comes back with its size but no image, since none of the
+/// decoders here can scale down while decoding.
+fn decode_art(art_data: &[u8]) -> Option<((u32, u32), Option<DynamicImage>)> {
+    let reader = || {
+        ImageReader::new(Cursor::new(art_data))
+            .with_guessed_format()
+            .ok()
+    };
+    if art_data.len() <= LARGE_ART_BYTES {
+        let image = reader()?.decode().ok()?;
+        let size = (image.width(), image.height());
+        return Some((size, Some(image)));
+    }
+
+    // Only the header is read for the size; the decoder then refuses anything bigger
+    // than the limits instead of allocating it
+    let size = reader()?.into_dimensions().ok()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(LARGE_ART_MAX_SIDE);
+    limits.max_image_height = Some(LARGE_ART_MAX_SIDE);
+    limits.max_alloc = Some(u64::from(LARGE_ART_MAX_SIDE).pow(2) * 4);
+    let image = reader().and_then(|mut reader| {
+        reader.limits(limits);
+        reader.decode().ok()
+    });
+    Some((
+        size,
+        image.map(|image| image.thumbnail(ART_THUMBNAIL_SIZE, ART_THUMBNAIL_SIZE)),
+    ))
+}
+
+/// Cell size assumed when neither the config nor the terminal provides one.
+const DEFAULT_FONT_SIZE: (u16, u16) = (10, 24);
+
+fn create_picker(config: &Config) -> Option<Picker> {
+    let query = Picker::from_query_stdio();
+    // A terminal that does not answer still gets half blocks, but one that cannot be
+    // talked to at all gets no art rather than keeping the editor from starting
+    if let Err(Errors::Io(_)) = query {
+        return None;
+    }
+
+    let picker = match config.font_size {
+        Some(font_size) => {
+            // Still ask the terminal which graphics protocol it speaks
+            let mut picker = Picker::from_fontsize(font_size);
+            if let Ok(queried) = query {
+                picker.set_protocol_type(queried.protocol_type());
+            }
+            picker
+        }
+        None => query.unwrap_or_else(|_| Picker::from_fontsize(DEFAULT_FONT_SIZE)),
+    };
+    Some(picker)
 }
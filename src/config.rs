@@ -0,0 +1,297 @@
+use ratatui_image::picker::ProtocolType;
+use ratatui_image::FilterType;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// User settings read from `config.toml` in the metamusic config directory, or the file
+/// named by `METAMUSIC_CONFIG`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Terminal cell size in pixels (width, height) used to lay out album art.
+    /// When unset it is queried from the terminal, falling back to 10×24.
+    pub font_size: Option<(u16, u16)>,
+    /// File extensions picked up when scanning, compared without regard to case.
+    pub extensions: Vec<String>,
+    /// Share of the width given to the files list, in percent.
+    pub files_width: Option<u16>,
+    /// Share of the right panel given to the tags preview, the rest going to the art.
+    pub preview_width: Option<u16>,
+    /// Whether operations touching several files wait for a y/n confirmation.
+    pub confirm_writes: bool,
+    /// Rows of the tags preview, in order, as (label, ID3 frame id).
+    pub preview_fields: Vec<(String, String)>,
+    /// Whether typed edits are held in memory until reviewed and saved together.
+    pub buffer_writes: bool,
+    /// With buffered writes, save them on their own this many seconds after the
+    /// oldest unsaved edit.
+    pub autosave_seconds: Option<u64>,
+    /// With buffered writes, save them on their own once this many are unsaved.
+    pub autosave_edits: Option<usize>,
+    /// What the art panel shows for a file without a cover: the name of a built-in
+    /// style from `PLACEHOLDER_STYLES`, or the path of a text file.
+    pub art_placeholder: Option<String>,
+    /// How album art is resampled to fit its cells: the name of one of `ART_FILTERS`.
+    pub art_filter: Option<String>,
+    /// The graphics protocol to draw art with instead of the one the terminal reports:
+    /// "halfblocks", "sixel", "kitty" or "iterm2".
+    pub art_protocol: Option<String>,
+    /// Covers per row of the gallery; when unset as many as fit.
+    pub gallery_columns: Option<u16>,
+    /// How tag values are made safe to use in file names when renaming.
+    pub filenames: FilenameRules,
+    /// Named find-and-replace rules to apply to a field, from `[[transforms]]` tables.
+    pub transforms: Vec<Transform>,
+}
+
+/// One `[[transforms]]` table: a regular expression replaced in one field.
+#[derive(Deserialize, Clone)]
+pub struct Transform {
+    pub name: String,
+    /// The field it changes, as named in the fields list ("Title" also works).
+    pub field: String,
+    /// The pattern, in the syntax of the regex-lite crate.
+    pub find: String,
+    /// What each match becomes, where `$1` or `${name}` bring back a group. Empty
+    /// to delete the matches.
+    #[serde(default)]
+    pub replace: String,
+}
+
+/// The `[filenames]` table: what renaming from a template does to the tag values it
+/// puts in a file name.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct FilenameRules {
+    /// Characters that may not appear in a file name. `/` is always refused.
+    pub forbidden: String,
+    /// Put in place of each forbidden character; may be empty to drop them.
+    pub replacement: String,
+    /// Whether runs of whitespace become a single space.
+    pub collapse_whitespace: bool,
+    /// Whether trailing dots and spaces are cut, as Windows does on its own.
+    pub trim_trailing: bool,
+}
+
+impl Default for FilenameRules {
+    fn default() -> Self {
+        FilenameRules {
+            forbidden: "<>:\"/\\|?*".to_string(),
+            replacement: "_".to_string(),
+            collapse_whitespace: true,
+            trim_trailing: true,
+        }
+    }
+}
+
+/// Narrowest and widest a panel may be made, in percent, so neither side vanishes.
+pub const SPLIT_RANGE: std::ops::RangeInclusive<u16> = 10..=90;
+
+/// Resampling filters for album art, fastest and blockiest first. The first is the
+/// default.
+pub const ART_FILTERS: &[(&str, FilterType)] = &[
+    ("nearest", FilterType::Nearest),
+    ("triangle", FilterType::Triangle),
+    ("catmull-rom", FilterType::CatmullRom),
+    ("gaussian", FilterType::Gaussian),
+    ("lanczos3", FilterType::Lanczos3),
+];
+
+/// Built-in drawings for files without a cover, the first being the default.
+pub const PLACEHOLDER_STYLES: &[(&str, &[&str])] = &[
+    (
+        "cd",
+        &[
+            "",
+            "╭───────────╮",
+            "│           │",
+            "│    ___    │",
+            "│   /   \\   │",
+            "│  | 📀 |   │",
+            "│   \\___/   │",
+            "│           │",
+            "╰───────────╯",
+            "",
+        ],
+    ),
+    ("minimal", &["", "No cover", ""]),
+    ("none", &[]),
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            font_size: None,
+            // Only MP3 can be tagged for now
+            extensions: vec!["mp3".to_string()],
+            files_width: None,
+            preview_width: None,
+            confirm_writes: true,
+            preview_fields: [
+                ("Title", "TIT2"),
+                ("Artist", "TPE1"),
+                ("Album", "TALB"),
+                ("Date", "TDRC"),
+                ("Track", "TRCK"),
+                ("Rating", "POPM"),
+            ]
+            .iter()
+            .map(|(label, frame)| (label.to_string(), frame.to_string()))
+            .collect(),
+            buffer_writes: false,
+            autosave_seconds: None,
+            autosave_edits: None,
+            art_placeholder: None,
+            art_filter: None,
+            art_protocol: None,
+            gallery_columns: None,
+            filenames: FilenameRules::default(),
+            transforms: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file named by `METAMUSIC_CONFIG`, or else the one in the config
+    /// directory, using the defaults when there is none. A file named explicitly must
+    /// exist.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        if let Some(path) = env::var_os("METAMUSIC_CONFIG").filter(|path| !path.is_empty()) {
+            return Self::read(&PathBuf::from(path));
+        }
+
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        Self::read(&path)
+    }
+
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// The lines drawn in place of a missing cover. A name that is not a built-in style
+    /// is read as a text file, which must exist.
+    pub fn art_placeholder(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let name = self
+            .art_placeholder
+            .as_deref()
+            .unwrap_or(PLACEHOLDER_STYLES[0].0);
+        if let Some((_, lines)) = PLACEHOLDER_STYLES.iter().find(|(style, _)| *style == name) {
+            return Ok(lines.iter().map(|line| line.to_string()).collect());
+        }
+        let contents =
+            fs::read_to_string(name).map_err(|e| format!("art_placeholder {}: {}", name, e))?;
+        Ok(contents.lines().map(str::to_string).collect())
+    }
+
+    /// The position of the configured filter in `ART_FILTERS`.
+    pub fn art_filter(&self) -> Result<usize, Box<dyn Error>> {
+        let Some(name) = self.art_filter.as_deref() else {
+            return Ok(0);
+        };
+        ART_FILTERS
+            .iter()
+            .position(|(filter, _)| filter.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names: Vec<&str> = ART_FILTERS.iter().map(|(filter, _)| *filter).collect();
+                format!(
+                    "art_filter '{}': expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+                .into()
+            })
+    }
+
+    pub fn art_protocol(&self) -> Result<Option<ProtocolType>, Box<dyn Error>> {
+        let Some(name) = self.art_protocol.as_deref() else {
+            return Ok(None);
+        };
+        let protocol = match name.to_ascii_lowercase().as_str() {
+            "halfblocks" => ProtocolType::Halfblocks,
+            "sixel" => ProtocolType::Sixel,
+            "kitty" => ProtocolType::Kitty,
+            "iterm2" => ProtocolType::Iterm2,
+            _ => {
+                return Err(format!(
+                    "art_protocol '{}': expected halfblocks, sixel, kitty or iterm2",
+                    name
+                )
+                .into())
+            }
+        };
+        Ok(Some(protocol))
+    }
+
+    pub fn files_width(&self) -> u16 {
+        split_or(self.files_width, 40)
+    }
+
+    pub fn preview_width(&self) -> u16 {
+        split_or(self.preview_width, 35)
+    }
+}
+
+fn split_or(percent: Option<u16>, default: u16) -> u16 {
+    percent
+        .filter(|percent| SPLIT_RANGE.contains(percent))
+        .unwrap_or(default)
+}
+
+/// What is remembered between runs, kept in `state.toml` in the metamusic state
+/// directory.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct State {
+    /// Whether the field list showed the advanced fields. The list itself is built
+    /// afresh each run, so fields added since the state was saved show up.
+    pub advanced_fields: bool,
+}
+
+impl State {
+    /// The saved state, or the defaults when there is none or it cannot be read: losing
+    /// it is no reason not to start.
+    pub fn load() -> Self {
+        state_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = state_path().ok_or("no state directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        fs::write(&path, toml::to_string(self)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(())
+    }
+}
+
+fn state_path() -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(state_dir.join("metamusic").join("state.toml"))
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("metamusic").join("config.toml"))
+}
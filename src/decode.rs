@@ -0,0 +1,61 @@
+use std::fs::File;
+use symphonia::core::codecs::audio::AudioDecoderOptions;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::probe::Hint;
+use symphonia::core::formats::{FormatOptions, TrackType};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+
+/// Decodes the whole file, handing each decoded buffer to `each` as interleaved
+/// samples along with the channel count and sample rate.
+pub fn decode(
+    path: &str,
+    mut each: impl FnMut(u32, u32, &[f32]) -> Result<(), String>,
+) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension("mp3");
+
+    let mut format = symphonia::default::get_probe()
+        .probe(
+            &hint,
+            source,
+            FormatOptions::default(),
+            MetadataOptions::default(),
+        )
+        .map_err(|e| e.to_string())?;
+    let track = format
+        .default_track(TrackType::Audio)
+        .ok_or("no audio track")?;
+    let track_id = track.id;
+    let params = track
+        .codec_params
+        .as_ref()
+        .and_then(|params| params.audio())
+        .ok_or("no audio track")?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make_audio_decoder(params, &AudioDecoderOptions::default())
+        .map_err(|e| e.to_string())?;
+
+    let mut samples: Vec<f32> = Vec::new();
+    while let Some(packet) = format.next_packet().map_err(|e| e.to_string())? {
+        if packet.track_id != track_id {
+            continue;
+        }
+        let buffer = match decoder.decode(&packet) {
+            Ok(buffer) => buffer,
+            // A damaged frame is skipped, as a player would
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let spec = buffer.spec();
+        let channels = spec.channels().count() as u32;
+        let rate = spec.rate();
+        samples.resize(buffer.samples_interleaved(), 0.0);
+        buffer.copy_to_slice_interleaved(&mut samples);
+        each(channels, rate, &samples)?;
+    }
+    Ok(())
+}
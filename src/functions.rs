@@ -1,36 +1,1000 @@
-use id3::{Tag, TagLike};
+use crate::config::{FilenameRules, Transform};
+
+use chrono::{DateTime, Local};
+use id3::frame::{Comment, ExtendedText, Lyrics, Picture, PictureType, Popularimeter};
+use id3::{ErrorKind, Frame, Tag, TagLike, Timestamp, Version};
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Fields always offered for editing.
+pub const BASIC_FIELDS: &[&str] = &["Song Name", "Artist", "Album", "Date", "Track", "Rating"];
+
+/// Columns of the table edit, one file per row.
+pub const TABLE_COLUMNS: &[&str] = &["Song Name", "Artist", "Album", "Track"];
+
+/// Fields copied between two copies of an album when matching them by track number.
+pub const COPY_BY_TRACK_FIELDS: &[&str] = &["Song Name", "Artist", "Album", "Date"];
+
+/// Structured fields offered in the advanced field set, ahead of the plain text ones.
+pub const ADVANCED_FIELDS: &[&str] = &["Original Date", "Key"];
+
+/// Plain text frames offered in the advanced field set, as (field name, frame id).
+pub const ADVANCED_TEXT_FIELDS: &[(&str, &str)] = &[
+    ("Album Artist", "TPE2"),
+    ("Genre", "TCON"),
+    ("Lyricist", "TEXT"),
+    ("Original Artist", "TOPE"),
+    ("Publisher", "TPUB"),
+    ("Encoded By", "TENC"),
+    ("Encoder Settings", "TSSE"),
+];
+
+/// What a field is stored as, for explaining the mapping to newcomers.
+pub struct FrameInfo {
+    pub field: &'static str,
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub format: &'static str,
+}
+
+pub const FRAME_INFO: &[FrameInfo] = &[
+    FrameInfo {
+        field: "Song Name",
+        id: "TIT2",
+        name: "Title/songname/content description",
+        description: "The actual name of the piece, as players show it.",
+        format: "Free text",
+    },
+    FrameInfo {
+        field: "Artist",
+        id: "TPE1",
+        name: "Lead performer(s)/Soloist(s)",
+        description: "The main artist of this track. Album artist lives in TPE2.",
+        format: "Free text, multiple artists separated by '/'",
+    },
+    FrameInfo {
+        field: "Album",
+        id: "TALB",
+        name: "Album/Movie/Show title",
+        description: "The title of the recording this track was released on.",
+        format: "Free text",
+    },
+    FrameInfo {
+        field: "Date",
+        id: "TDRC",
+        name: "Recording time",
+        description: "When the recording was made. ID3v2.4 merged the older TYER, TDAT and \
+                      TIME frames into this single timestamp.",
+        format: "YYYY, YYYY-MM, YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS",
+    },
+    FrameInfo {
+        field: "Track",
+        id: "TRCK",
+        name: "Track number/Position in set",
+        description: "The position of this track on its disc.",
+        format: "Number, optionally with the total: 3 or 3/12",
+    },
+    FrameInfo {
+        field: "Rating",
+        id: "POPM",
+        name: "Popularimeter",
+        description: "A rating and play counter tied to an email address, so several players \
+                      can keep their own.",
+        format: "Rating byte 0-255, shown here as 0 to 5 stars",
+    },
+    FrameInfo {
+        field: "Original Date",
+        id: "TDOR",
+        name: "Original release time",
+        description: "When the original recording was first released, for remasters and \
+                      reissues whose own date is later. ID3v2.3 only keeps the year, in TORY.",
+        format: "YYYY, YYYY-MM, YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS",
+    },
+    FrameInfo {
+        field: "Key",
+        id: "TKEY",
+        name: "Initial key",
+        description: "The musical key the track starts in, for DJs mixing harmonically.",
+        format: "A note and mode such as Am, F#, Bbm or C major, Camelot such as 8A, \
+                 or o for off-key",
+    },
+    FrameInfo {
+        field: "Album Artist",
+        id: "TPE2",
+        name: "Band/orchestra/accompaniment",
+        description: "The artist of the album as a whole, which players group albums by \
+                      when the tracks have different artists.",
+        format: "Free text",
+    },
+    FrameInfo {
+        field: "Genre",
+        id: "TCON",
+        name: "Content type",
+        description: "The genre. Old taggers store an ID3v1 genre number such as (17).",
+        format: "Free text, or a number in brackets",
+    },
+    FrameInfo {
+        field: "Lyricist",
+        id: "TEXT",
+        name: "Lyricist/Text writer",
+        description: "The writer of the lyrics or text.",
+        format: "Free text",
+    },
+    FrameInfo {
+        field: "Original Artist",
+        id: "TOPE",
+        name: "Original artist(s)/performer(s)",
+        description: "The performer of the original recording, for covers and remixes.",
+        format: "Free text",
+    },
+    FrameInfo {
+        field: "Publisher",
+        id: "TPUB",
+        name: "Publisher",
+        description: "The label or publisher of the recording.",
+        format: "Free text",
+    },
+    FrameInfo {
+        field: "Encoded By",
+        id: "TENC",
+        name: "Encoded by",
+        description: "The person or organisation that encoded the file.",
+        format: "Free text",
+    },
+    FrameInfo {
+        field: "Encoder Settings",
+        id: "TSSE",
+        name: "Software/Hardware and settings used for encoding",
+        description: "The encoder and the options it was run with, such as LAME 3.100 -V0.",
+        format: "Free text",
+    },
+];
+
+pub fn frame_info(field: &str) -> Option<&'static FrameInfo> {
+    FRAME_INFO.iter().find(|info| info.field == field)
+}
+
+/// How the value of a structured field is typed in.
+pub struct FieldInput {
+    /// Expected format, shown while editing.
+    pub hint: &'static str,
+    /// Characters that can appear in a value; anything else is ignored as it is typed.
+    pub accepts: fn(char) -> bool,
+    /// Whether a finished value will be accepted on save.
+    pub is_valid: fn(&str) -> bool,
+}
+
+const FIELD_INPUTS: &[(&str, FieldInput)] = &[
+    (
+        "Date",
+        FieldInput {
+            hint: "YYYY or YYYY-MM-DD",
+            accepts: |c| c.is_ascii_digit() || matches!(c, '-' | 'T' | ':'),
+            is_valid: |value| parse_date(value).is_ok(),
+        },
+    ),
+    (
+        "Original Date",
+        FieldInput {
+            hint: "YYYY or YYYY-MM-DD, empty to remove",
+            accepts: |c| c.is_ascii_digit() || matches!(c, '-' | 'T' | ':'),
+            is_valid: |value| value.trim().is_empty() || parse_date(value).is_ok(),
+        },
+    ),
+    (
+        "Key",
+        FieldInput {
+            hint: "a key such as Am, F#, Bbm or 8A, empty to remove",
+            accepts: |c| !c.is_control(),
+            is_valid: |value| value.trim().is_empty() || is_musical_key(value),
+        },
+    ),
+    (
+        "Track",
+        FieldInput {
+            hint: "number or n/total",
+            accepts: |c| c.is_ascii_digit() || c == '/',
+            is_valid: |value| parse_track(value).is_ok(),
+        },
+    ),
+    (
+        "Rating",
+        FieldInput {
+            hint: "0 to 5 stars",
+            accepts: |c| ('0'..='5').contains(&c),
+            is_valid: |value| value.trim().parse::<usize>().is_ok_and(|stars| stars <= 5),
+        },
+    ),
+];
+
+/// Input rules for `field`, or `None` for free text.
+pub fn field_input(field: &str) -> Option<&'static FieldInput> {
+    FIELD_INPUTS
+        .iter()
+        .find(|(name, _)| *name == field)
+        .map(|(_, input)| input)
+}
+
+/// Whether `value` reads as a musical key: a note with an optional accidental and mode
+/// ("Am", "F#", "Bbm", "C major"), a Camelot or Open Key code ("8A", "1m"), or "o" for
+/// off-key. Spellings vary between tools, so the check is loose.
+pub fn is_musical_key(value: &str) -> bool {
+    let value = value.trim();
+    if value == "o" {
+        return true;
+    }
+
+    // Camelot (1A-12B) and Open Key (1m-12d) wheels
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, letter) = value.split_at(split);
+    if let Ok(number) = number.parse::<u32>() {
+        return (1..=12).contains(&number)
+            && matches!(letter.to_ascii_lowercase().as_str(), "a" | "b" | "m" | "d");
+    }
+
+    let mut chars = value.chars();
+    if !chars
+        .next()
+        .is_some_and(|note| ('A'..='G').contains(&note.to_ascii_uppercase()))
+    {
+        return false;
+    }
+    let rest = chars.as_str();
+    let mode = rest
+        .strip_prefix(['#', 'b', '♯', '♭'])
+        .unwrap_or(rest)
+        .trim_start();
+    matches!(
+        mode.to_ascii_lowercase().as_str(),
+        "" | "m" | "min" | "minor" | "maj" | "major"
+    )
+}
 
-pub fn get_mp3_files(dir: &str) -> Result<Vec<String>, Box<dyn Error>> {
-    let mut mp3_files = Vec::new();
+/// Identifier stored in the POPM frames metamusic writes.
+pub const RATING_EMAIL: &str = "metamusic";
+
+/// POPM rating bytes for 0 to 5 stars, as written by most players.
+const RATING_BYTES: [u8; 6] = [0, 1, 64, 128, 196, 255];
+
+/// Cover images commonly kept next to the tracks of an album, in lookup order.
+pub const SIDECAR_ART_NAMES: &[&str] = &[
+    "cover.jpg",
+    "cover.png",
+    "folder.jpg",
+    "folder.png",
+    "front.jpg",
+    "front.png",
+];
+
+/// Files found by a scan, with the entries that had to be skipped.
+#[derive(Default)]
+pub struct Scan {
+    pub files: Vec<String>,
+    /// Why each skipped directory or entry could not be listed.
+    pub warnings: Vec<String>,
+}
+
+/// Lists the files in `dir` whose extension is one of `extensions`, ignoring case, or
+/// with `probe` also those holding MP3 audio under another name. Symlinks to files are
+/// listed under the link's name; subdirectories, linked or not, are never entered.
+/// Entries that cannot be read are skipped with a warning rather than failing the scan.
+pub fn get_music_files(dir: &str, extensions: &[String], probe: bool) -> Scan {
+    let mut scan = Scan::default();
     let path = Path::new(dir);
 
     if path.is_dir() {
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                scan.warnings.push(format!("{}: {}", dir, e));
+                return scan;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    scan.warnings.push(format!("{}: {}", dir, e));
+                    continue;
+                }
+            };
             let path = entry.path();
             if path.is_file()
-                && let Some(ext) = path.extension()
-                && ext == "mp3"
-                && let Some(filename) = path.file_name().and_then(|s| s.to_str())
+                && (has_extension(&path, extensions) || probe && is_mp3_content(&path))
+            {
+                match path.file_name().and_then(|s| s.to_str()) {
+                    Some(filename) => scan.files.push(filename.to_string()),
+                    None => scan
+                        .warnings
+                        .push(format!("{}: name is not valid UTF-8", path.display())),
+                }
+            }
+        }
+    }
+
+    scan.files.sort();
+    scan
+}
+
+/// Whether `path` ends in one of `extensions`, compared without regard to case.
+pub fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|wanted| wanted.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        })
+}
+
+/// Sniffs the start of the file for MP3 audio: an ID3v2 header followed by a frame,
+/// or a bare MPEG audio frame header.
+pub fn is_mp3_content(path: &Path) -> bool {
+    fs::File::open(path)
+        .ok()
+        .and_then(|mut file| first_frame(&mut file))
+        .is_some()
+}
+
+/// Offset and header of the first audio frame, just past any ID3v2 tag.
+fn first_frame(file: &mut fs::File) -> Option<(u64, [u8; 4])> {
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header).ok()?;
+
+    let mut offset = 0;
+    if header.starts_with(b"ID3") {
+        // The tag size is syncsafe: seven bits per byte
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |size, byte| (size << 7) | u64::from(byte & 0x7F));
+        offset = 10 + size;
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        file.read_exact(&mut header[..4]).ok()?;
+    }
+    is_frame_header(&header[..3]).then(|| (offset, [header[0], header[1], header[2], header[3]]))
+}
+
+/// Playing time of an MP3, from the frame count of a Xing/Info or VBRI header when
+/// there is one, or else from the bitrate of the first frame. Only Layer III is read.
+pub fn mp3_duration(path: &Path) -> Option<Duration> {
+    const BITRATES_V1: [u32; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const BITRATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+    let mut file = fs::File::open(path).ok()?;
+    let length = file.metadata().ok()?.len();
+    let (offset, header) = first_frame(&mut file)?;
+
+    let version = (header[1] >> 3) & 0x03;
+    if (header[1] >> 1) & 0x03 != 1 || version == 1 {
+        return None;
+    }
+    let mpeg1 = version == 3;
+    let sample_rate = match ((header[2] >> 2) & 0x03, version) {
+        (3, _) => return None,
+        (index, 3) => [44100, 48000, 32000][index as usize],
+        (index, 2) => [22050, 24000, 16000][index as usize],
+        (index, _) => [11025, 12000, 8000][index as usize],
+    };
+    let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+    let mono = header[3] >> 6 == 3;
+
+    // A VBR file announces its frame count in the first frame
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let mut info = [0u8; 50];
+    file.seek(SeekFrom::Start(offset + 4)).ok()?;
+    let read = file.read(&mut info).ok()?;
+    let be_u32 =
+        |at: usize| u32::from_be_bytes([info[at], info[at + 1], info[at + 2], info[at + 3]]);
+    let frames = if read >= side_info + 12
+        && matches!(&info[side_info..side_info + 4], b"Xing" | b"Info")
+        && info[side_info + 7] & 0x01 != 0
+    {
+        Some(be_u32(side_info + 8))
+    } else if read >= 50 && &info[32..36] == b"VBRI" {
+        Some(be_u32(46))
+    } else {
+        None
+    };
+    if let Some(frames) = frames {
+        return Some(Duration::from_secs_f64(
+            f64::from(frames) * f64::from(samples_per_frame) / f64::from(sample_rate),
+        ));
+    }
+
+    let bitrates = if mpeg1 { BITRATES_V1 } else { BITRATES_V2 };
+    let bitrate = *bitrates.get((header[2] >> 4) as usize)?;
+    if bitrate == 0 {
+        return None;
+    }
+    let audio_bytes = length.saturating_sub(offset);
+    Some(Duration::from_secs_f64(
+        audio_bytes as f64 * 8.0 / (f64::from(bitrate) * 1000.0),
+    ))
+}
+
+/// Frame sync, a known MPEG layer and a usable bitrate.
+fn is_frame_header(bytes: &[u8]) -> bool {
+    bytes[0] == 0xFF
+        && bytes[1] & 0xE0 == 0xE0
+        && (bytes[1] >> 1) & 0x03 != 0
+        && bytes[2] >> 4 != 0x0F
+}
+
+/// Lists the music files under each of `roots`, prefixed with their root. Without any
+/// roots the current directory is scanned and bare file names are returned. A file
+/// reachable through overlapping roots is only listed once. With `probe`, files that
+/// hold MP3 audio are listed whatever their extension.
+pub fn scan_roots(roots: &[String], extensions: &[String], probe: bool) -> Scan {
+    if roots.is_empty() {
+        return get_music_files(".", extensions, probe);
+    }
+
+    let mut seen = HashSet::new();
+    let mut scan = Scan::default();
+    for root in roots {
+        let found = get_music_files(root, extensions, probe);
+        scan.warnings.extend(found.warnings);
+        for name in found.files {
+            let path = Path::new(root).join(&name);
+            let identity = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if seen.insert(identity)
+                && let Some(path) = path.to_str()
             {
-                mp3_files.push(filename.to_string());
+                scan.files.push(path.to_string());
             }
         }
     }
 
-    mp3_files.sort();
-    Ok(mp3_files)
+    scan.files.sort();
+    scan
+}
+
+/// Every field that can be edited, basic ones first.
+pub fn all_fields() -> impl Iterator<Item = &'static str> {
+    BASIC_FIELDS
+        .iter()
+        .chain(ADVANCED_FIELDS)
+        .copied()
+        .chain(ADVANCED_TEXT_FIELDS.iter().map(|(field, _)| *field))
+}
+
+/// The editable field called `name`, ignoring case. "Title" is taken for "Song Name",
+/// as the preview labels it.
+pub fn find_field(name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    if name.eq_ignore_ascii_case("title") {
+        return Some("Song Name");
+    }
+    all_fields().find(|field| field.eq_ignore_ascii_case(name))
 }
 
-pub fn modify_field(file_path: &str, field: &str, value: &str) -> Result<(), Box<dyn Error>> {
-    let mut tag = match Tag::read_from_path(file_path) {
+/// Text frames already exported as part of a field.
+const FIELD_FRAMES: &[&str] = &[
+    "TIT2", "TPE1", "TALB", "TDRC", "TYER", "TDAT", "TIME", "TDOR", "TORY", "TRCK", "TKEY",
+];
+
+/// The tags of one file as written by `export_json` and read by `plan_import`.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TagExport {
+    pub file: String,
+    /// Editable fields by name, as the editor shows them; missing ones are left out.
+    pub fields: BTreeMap<String, String>,
+    /// Every other text frame by id, such as TCON (genre) or TPOS (disc).
+    pub frames: BTreeMap<String, String>,
+    /// User-defined TXXX frames by description, such as the ReplayGain ones.
+    pub extended: BTreeMap<String, String>,
+    pub comments: Vec<TextEntry>,
+    pub lyrics: Vec<TextEntry>,
+    /// Why the tag could not be read, in which case everything else is empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A COMM or USLT frame, which can span several lines.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct TextEntry {
+    pub lang: String,
+    pub description: String,
+    pub text: String,
+}
+
+fn export_tag(file: &str) -> TagExport {
+    let mut export = TagExport {
+        file: file.to_string(),
+        ..TagExport::default()
+    };
+    let tag = match Tag::read_from_path(file) {
         Ok(tag) => tag,
-        Err(_) => Tag::new(),
+        Err(e) if matches!(e.kind, ErrorKind::NoTag) => return export,
+        Err(e) => {
+            export.error = Some(e.to_string());
+            return export;
+        }
     };
 
+    for field in all_fields() {
+        let value = field_value(&tag, field);
+        if !value.is_empty() {
+            export.fields.insert(field.to_string(), value);
+        }
+    }
+    let field_frames: HashSet<&str> = FIELD_FRAMES
+        .iter()
+        .copied()
+        .chain(ADVANCED_TEXT_FIELDS.iter().map(|(_, id)| *id))
+        .collect();
+    for frame in tag.frames() {
+        if let Some(text) = frame.content().text()
+            && !field_frames.contains(frame.id())
+        {
+            export
+                .frames
+                .insert(frame.id().to_string(), text.to_string());
+        }
+    }
+    for extended in tag.extended_texts() {
+        export
+            .extended
+            .insert(extended.description.clone(), extended.value.clone());
+    }
+    export.comments = comment_entries(&tag);
+    export.lyrics = lyrics_entries(&tag);
+    export
+}
+
+fn comment_entries(tag: &Tag) -> Vec<TextEntry> {
+    tag.comments()
+        .map(|comment| TextEntry {
+            lang: comment.lang.clone(),
+            description: comment.description.clone(),
+            text: comment.text.clone(),
+        })
+        .collect()
+}
+
+fn lyrics_entries(tag: &Tag) -> Vec<TextEntry> {
+    tag.lyrics()
+        .map(|lyrics| TextEntry {
+            lang: lyrics.lang.clone(),
+            description: lyrics.description.clone(),
+            text: lyrics.text.clone(),
+        })
+        .collect()
+}
+
+/// One difference between a file and its entry in an import, shown as
+/// (what, old, new) before it is applied.
+pub enum ImportEdit {
+    Field(String, String, String),
+    Frame(String, String, String),
+    Extended(String, String, String),
+    Comments(Vec<TextEntry>),
+    Lyrics(Vec<TextEntry>),
+}
+
+impl ImportEdit {
+    /// What changes, and its old and new value, for the preview.
+    pub fn describe(&self) -> (String, String, String) {
+        let count = |entries: &Vec<TextEntry>, what: &str| match entries.first() {
+            Some(entry) if entries.len() == 1 => {
+                entry.text.lines().next().unwrap_or("").to_string()
+            }
+            _ => format!("{} {}", entries.len(), what),
+        };
+        match self {
+            ImportEdit::Field(field, old, new) => (field.clone(), old.clone(), new.clone()),
+            ImportEdit::Frame(id, old, new) => (id.clone(), old.clone(), new.clone()),
+            ImportEdit::Extended(description, old, new) => {
+                (format!("TXXX:{}", description), old.clone(), new.clone())
+            }
+            ImportEdit::Comments(entries) => (
+                "Comments".to_string(),
+                String::new(),
+                count(entries, "comments"),
+            ),
+            ImportEdit::Lyrics(entries) => (
+                "Lyrics".to_string(),
+                String::new(),
+                count(entries, "lyrics"),
+            ),
+        }
+    }
+}
+
+/// What importing a JSON file would do, worked out before anything is written.
+pub struct ImportPlan {
+    /// Each file that would change, with its edits.
+    pub changes: Vec<(String, Vec<ImportEdit>)>,
+    /// Files the JSON names that do not exist.
+    pub missing: Vec<String>,
+    /// Values that would be refused, as (file, problem).
+    pub invalid: Vec<(String, String)>,
+}
+
+/// Reads a file written by `export_json` and compares it with the files it names,
+/// which are looked for as given and then next to the JSON file. The whole file must
+/// parse, so a broken one changes nothing.
+pub fn plan_import(path: &Path) -> Result<ImportPlan, Box<dyn Error>> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let entries: Vec<TagExport> = serde_json::from_str(&contents)
+        .map_err(|e| format!("{} is not a metamusic export: {}", path.display(), e))?;
+
+    let mut plan = ImportPlan {
+        changes: Vec::new(),
+        missing: Vec::new(),
+        invalid: Vec::new(),
+    };
+    for entry in entries {
+        // Entries of files that were unreadable when exported hold nothing to restore
+        if entry.error.is_some() {
+            continue;
+        }
+        let beside = path.parent().map(|dir| dir.join(&entry.file));
+        let file = if Path::new(&entry.file).is_file() {
+            entry.file.clone()
+        } else if let Some(beside) = beside.filter(|beside| beside.is_file()) {
+            beside.display().to_string()
+        } else {
+            plan.missing.push(entry.file);
+            continue;
+        };
+        let tag = match read_tag_for_write(&file) {
+            Ok(tag) => tag,
+            Err(e) => {
+                plan.invalid.push((file, e.to_string()));
+                continue;
+            }
+        };
+
+        let mut edits = Vec::new();
+        for (name, new) in &entry.fields {
+            let Some(field) = find_field(name) else {
+                plan.invalid
+                    .push((file.clone(), format!("unknown field '{}'", name)));
+                continue;
+            };
+            if let Some(input) = field_input(field)
+                && !(input.is_valid)(new)
+            {
+                plan.invalid.push((
+                    file.clone(),
+                    format!("{} '{}', expected {}", field, new, input.hint),
+                ));
+                continue;
+            }
+            let old = field_value(&tag, field);
+            if old != *new {
+                edits.push(ImportEdit::Field(field.to_string(), old, new.clone()));
+            }
+        }
+        for (id, new) in &entry.frames {
+            let valid = id.len() == 4
+                && id.starts_with('T')
+                && id.chars().all(|c| c.is_ascii_alphanumeric());
+            if !valid || FIELD_FRAMES.contains(&id.as_str()) || id == "TXXX" {
+                plan.invalid
+                    .push((file.clone(), format!("cannot set frame '{}'", id)));
+                continue;
+            }
+            let old = tag
+                .get(id)
+                .and_then(|frame| frame.content().text())
+                .unwrap_or("")
+                .to_string();
+            if old != *new {
+                edits.push(ImportEdit::Frame(id.clone(), old, new.clone()));
+            }
+        }
+        for (description, new) in &entry.extended {
+            let old = tag
+                .extended_texts()
+                .find(|extended| extended.description == *description)
+                .map(|extended| extended.value.clone())
+                .unwrap_or_default();
+            if old != *new {
+                edits.push(ImportEdit::Extended(description.clone(), old, new.clone()));
+            }
+        }
+        if !entry.comments.is_empty() && entry.comments != comment_entries(&tag) {
+            edits.push(ImportEdit::Comments(entry.comments));
+        }
+        if !entry.lyrics.is_empty() && entry.lyrics != lyrics_entries(&tag) {
+            edits.push(ImportEdit::Lyrics(entry.lyrics));
+        }
+
+        if !edits.is_empty() {
+            plan.changes.push((file, edits));
+        }
+    }
+    Ok(plan)
+}
+
+/// Applies the edits `plan_import` found for one file, unless it has changed on disk
+/// since `expected` as with `modify_field`.
+pub fn import_json(
+    file: &str,
+    edits: &[ImportEdit],
+    expected: Option<SystemTime>,
+) -> Result<(), Box<dyn Error>> {
+    check_unchanged(file, expected)?;
+    // Fields go through the editor's own rules, the rest is set frame by frame
+    for edit in edits {
+        if let ImportEdit::Field(field, _, new) = edit {
+            modify_field(file, field, new, None)?;
+        }
+    }
+    if edits
+        .iter()
+        .all(|edit| matches!(edit, ImportEdit::Field(..)))
+    {
+        return Ok(());
+    }
+
+    let mut tag = read_tag_for_write(file)?;
+    for edit in edits {
+        match edit {
+            ImportEdit::Field(..) => {}
+            ImportEdit::Frame(id, _, new) => tag.set_text(id, new.clone()),
+            ImportEdit::Extended(description, _, new) => {
+                tag.remove_extended_text(Some(description), None);
+                tag.add_frame(ExtendedText {
+                    description: description.clone(),
+                    value: new.clone(),
+                });
+            }
+            ImportEdit::Comments(entries) => {
+                tag.remove("COMM");
+                for entry in entries {
+                    tag.add_frame(Comment {
+                        lang: entry.lang.clone(),
+                        description: entry.description.clone(),
+                        text: entry.text.clone(),
+                    });
+                }
+            }
+            ImportEdit::Lyrics(entries) => {
+                tag.remove("USLT");
+                for entry in entries {
+                    tag.add_frame(Lyrics {
+                        lang: entry.lang.clone(),
+                        description: entry.description.clone(),
+                        text: entry.text.clone(),
+                    });
+                }
+            }
+        }
+    }
+    write_tag(&tag, file)
+}
+
+/// Writes the tags of `files` to `out_path` as a JSON array, one object per file, and
+/// returns how many could not be read.
+pub fn export_json(files: &[String], out_path: &Path) -> Result<usize, Box<dyn Error>> {
+    let exports: Vec<TagExport> = files.iter().map(|file| export_tag(file)).collect();
+    let unreadable = exports
+        .iter()
+        .filter(|export| export.error.is_some())
+        .count();
+    let json = serde_json::to_string_pretty(&exports)?;
+    fs::write(out_path, json + "\n")
+        .map_err(|e| format!("cannot write {}: {}", out_path.display(), e))?;
+    Ok(unreadable)
+}
+
+pub fn text_frame_id(field: &str) -> Option<&'static str> {
+    ADVANCED_TEXT_FIELDS
+        .iter()
+        .find(|(name, _)| *name == field)
+        .map(|(_, id)| *id)
+}
+
+/// Opening for append succeeds exactly when a later tag write would be allowed to open the file.
+pub fn is_writable(file_path: &str) -> bool {
+    fs::OpenOptions::new().append(true).open(file_path).is_ok()
+}
+
+/// The editable value of `field`, empty when the frame is missing.
+pub fn field_value(tag: &Tag, field: &str) -> String {
+    match field {
+        "Song Name" => tag.title().unwrap_or("").to_string(),
+        "Artist" => tag.artist().unwrap_or("").to_string(),
+        "Album" => tag.album().unwrap_or("").to_string(),
+        "Date" => tag
+            .date_recorded()
+            .or_else(|| v23_date(tag))
+            .map(|date| date.to_string())
+            .unwrap_or_default(),
+        "Original Date" => tag
+            .original_date_released()
+            .map(|date| date.to_string())
+            .or_else(|| {
+                tag.get("TORY")
+                    .and_then(|frame| frame.content().text())
+                    .map(str::to_string)
+            })
+            .unwrap_or_default(),
+        "Key" => tag
+            .get("TKEY")
+            .and_then(|frame| frame.content().text())
+            .unwrap_or("")
+            .to_string(),
+        "Track" => track_text(tag).unwrap_or_default(),
+        "Rating" => rating_stars(tag)
+            .map(|stars| stars.to_string())
+            .unwrap_or_default(),
+        _ => text_frame_id(field)
+            .and_then(|id| tag.get(id))
+            .and_then(|frame| frame.content().text())
+            .unwrap_or("")
+            .to_string(),
+    }
+}
+
+/// Reads the tag that an edit will be applied to. Only a file without any tag starts
+/// from an empty one: writing back a tag that failed to read would drop every frame
+/// that could not be decoded.
+pub fn read_tag_for_write(file_path: &str) -> Result<Tag, Box<dyn Error>> {
+    match Tag::read_from_path(file_path) {
+        Ok(tag) => Ok(tag),
+        Err(e) if matches!(e.kind, ErrorKind::NoTag) => Ok(Tag::new()),
+        Err(e) => Err(format!("could not read the existing tag: {}", e).into()),
+    }
+}
+
+/// The track number as stored, with the total when there is one: "3" or "3/12".
+pub fn track_text(tag: &Tag) -> Option<String> {
+    let track = tag.track()?;
+    Some(match tag.total_tracks() {
+        Some(total) => format!("{}/{}", track, total),
+        None => track.to_string(),
+    })
+}
+
+#[derive(Clone)]
+pub struct TagInfo {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub track: Option<u32>,
+    pub total_tracks: Option<u32>,
+    /// The configured preview rows, as (label, frame id, value).
+    pub preview: Vec<(String, String, String)>,
+    pub has_art: bool,
+    /// The type of each embedded picture, in the order they are stored.
+    pub picture_types: Vec<String>,
+}
+
+/// Reads the tags of a file for display. A file without any tag is shown with empty
+/// tags; `None` means the tag is there but could not be parsed.
+pub fn read_tag_info(
+    filename: &str,
+    preview_fields: &[(String, String)],
+) -> Result<TagInfo, String> {
+    let tag = match Tag::read_from_path(filename) {
+        Ok(tag) => tag,
+        Err(e) if matches!(e.kind, ErrorKind::NoTag) => Tag::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut preview: Vec<(String, String, String)> = preview_fields
+        .iter()
+        .map(|(label, frame)| (label.clone(), frame.clone(), preview_value(&tag, frame)))
+        .collect();
+    // A reissue's original date and a DJ's key are worth seeing even when the preview
+    // does not list them, each after the row it belongs with
+    for (field, frame, after) in [("Original Date", "TDOR", "TDRC"), ("Key", "TKEY", "TRCK")] {
+        let value = field_value(&tag, field);
+        if value.is_empty() || preview.iter().any(|(_, shown, _)| shown == frame) {
+            continue;
+        }
+        let position = preview
+            .iter()
+            .position(|(_, shown, _)| shown == after)
+            .map_or(preview.len(), |index| index + 1);
+        preview.insert(position, (field.to_string(), frame.to_string(), value));
+    }
+
+    // Missing values stay empty, the UI decides how to show them
+    Ok(TagInfo {
+        title: field_value(&tag, "Song Name"),
+        artist: field_value(&tag, "Artist"),
+        album: field_value(&tag, "Album"),
+        track: tag.track(),
+        total_tracks: tag.total_tracks(),
+        preview,
+        has_art: tag.pictures().next().is_some(),
+        picture_types: tag
+            .pictures()
+            .map(|picture| picture.picture_type.to_string())
+            .collect(),
+    })
+}
+
+/// The value of `frame` as the preview shows it.
+fn preview_value(tag: &Tag, frame: &str) -> String {
+    match frame {
+        "POPM" => rating_stars(tag)
+            .map(|stars| {
+                let stars = stars as usize;
+                format!("{}{}", "★".repeat(stars), "☆".repeat(5 - stars))
+            })
+            .unwrap_or_default(),
+        // Also covers the v2.3 TYER/TDAT frames, and the total of tracks
+        "TDRC" => field_value(tag, "Date"),
+        "TRCK" => field_value(tag, "Track"),
+        "TDOR" => field_value(tag, "Original Date"),
+        id => tag
+            .get(id)
+            .and_then(|frame| frame.content().text())
+            .unwrap_or("")
+            .to_string(),
+    }
+}
+
+/// Why `modify_field` refused to write: another program changed the file since it
+/// was read.
+#[derive(Debug)]
+pub struct ChangedOnDisk(pub String);
+
+impl fmt::Display for ChangedOnDisk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} was changed by another program since it was read",
+            self.0
+        )
+    }
+}
+
+impl Error for ChangedOnDisk {}
+
+/// When `path` was last modified, if the filesystem says.
+pub fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Fails with a `ChangedOnDisk` when `file_path` no longer has the modification time
+/// `expected`, the one it had when it was read.
+fn check_unchanged(file_path: &str, expected: Option<SystemTime>) -> Result<(), Box<dyn Error>> {
+    if let Some(expected) = expected
+        && modified_time(file_path) != Some(expected)
+    {
+        return Err(Box::new(ChangedOnDisk(file_path.to_string())));
+    }
+    Ok(())
+}
+
+/// Writes `value` to `field` of the tag of `file_path`. With `expected`, the modification
+/// time the file had when it was read, nothing is written if the file has changed since,
+/// and the error is a `ChangedOnDisk`.
+pub fn modify_field(
+    file_path: &str,
+    field: &str,
+    value: &str,
+    expected: Option<SystemTime>,
+) -> Result<(), Box<dyn Error>> {
+    check_unchanged(file_path, expected)?;
+    let mut tag = read_tag_for_write(file_path)?;
+
     match field {
         "Song Name" => {
             tag.set_title(value);
@@ -42,18 +1006,1436 @@ pub fn modify_field(file_path: &str, field: &str, value: &str) -> Result<(), Box
             tag.set_album(value);
         }
         "Date" => {
-            if let Ok(year) = value.parse() {
-                tag.set_date_recorded(year);
+            let date = parse_date(value)?;
+            if tag.version() == Version::Id3v23 {
+                set_v23_date(&mut tag, date);
+            } else {
+                tag.set_date_recorded(date);
+            }
+        }
+        "Original Date" => {
+            tag.remove_original_date_released();
+            tag.remove("TORY");
+            if !value.trim().is_empty() {
+                let date = parse_date(value)?;
+                if tag.version() == Version::Id3v23 {
+                    tag.set_text("TORY", date.year.to_string());
+                } else {
+                    tag.set_original_date_released(date);
+                }
             }
         }
         "Track" => {
-            if let Ok(track) = value.parse() {
-                tag.set_track(track);
+            // "3/12" sets the total as well, a bare "3" drops it
+            let (track, total) = parse_track(value)?;
+            tag.set_track(track);
+            match total {
+                Some(total) => tag.set_total_tracks(total),
+                None => tag.remove_total_tracks(),
+            }
+        }
+        "Key" => {
+            let key = value.trim();
+            if key.is_empty() {
+                tag.remove("TKEY");
+            } else if is_musical_key(key) {
+                tag.set_text("TKEY", key);
+            } else {
+                return Err(format!("'{}' is not a musical key", key).into());
+            }
+        }
+        "Rating" => {
+            let stars: usize = match value.trim().parse() {
+                Ok(stars) if stars <= 5 => stars,
+                _ => return Err(format!("invalid rating '{}', expected 0 to 5", value).into()),
+            };
+            // Keep the play counter of an existing rating
+            let counter = popularimeter(&tag).map(|popm| popm.counter).unwrap_or(0);
+            tag.add_frame(Popularimeter {
+                user: RATING_EMAIL.to_string(),
+                rating: RATING_BYTES[stars],
+                counter,
+            });
+        }
+        _ => {
+            if let Some(id) = text_frame_id(field) {
+                // An empty value removes the frame rather than leaving an empty one behind
+                if value.is_empty() {
+                    tag.remove(id);
+                } else {
+                    tag.set_text(id, value);
+                }
             }
         }
-        _ => {}
     }
 
-    tag.write_to_path(file_path, id3::Version::Id3v24)?;
-    Ok(())
+    write_tag(&tag, file_path)
+}
+
+/// The date of a tag without TDRC, put together from the year (TYER), date (DDMM in
+/// TDAT) and time (HHMM in TIME) frames that ID3v2.3 keeps it in.
+fn v23_date(tag: &Tag) -> Option<Timestamp> {
+    let pair = |id: &str| -> Option<(u8, u8)> {
+        let text = tag.get(id)?.content().text()?;
+        if text.len() != 4 {
+            return None;
+        }
+        Some((text.get(..2)?.parse().ok()?, text.get(2..)?.parse().ok()?))
+    };
+    let (day, month) = pair("TDAT").unzip();
+    let (hour, minute) = pair("TIME").filter(|_| day.is_some()).unzip();
+    Some(Timestamp {
+        year: tag.year()?,
+        month,
+        day,
+        hour,
+        minute,
+        second: None,
+    })
+}
+
+/// ID3v2.3 has no TDRC, and a lot of hardware players only look at its separate
+/// year (YYYY), date (DDMM) and time (HHMM) frames.
+fn set_v23_date(tag: &mut Tag, date: Timestamp) {
+    tag.remove_date_recorded();
+    tag.set_year(date.year);
+    tag.remove("TDAT");
+    tag.remove("TIME");
+    if let (Some(month), Some(day)) = (date.month, date.day) {
+        tag.set_text("TDAT", format!("{:02}{:02}", day, month));
+    }
+    if let (Some(hour), Some(minute)) = (date.hour, date.minute) {
+        tag.set_text("TIME", format!("{:02}{:02}", hour, minute));
+    }
+}
+
+/// Writes `tag` to a copy of the file next to it and renames the copy over the original,
+/// so a crash mid-write can never leave a half-written file behind. A symlink is
+/// resolved first: renaming over the link itself would replace it with a copy and
+/// leave the real file untouched.
+pub fn write_tag(tag: &Tag, file_path: &str) -> Result<(), Box<dyn Error>> {
+    let real_path = fs::canonicalize(file_path)?;
+    let path = real_path.as_path();
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("invalid file path '{}'", file_path))?;
+    let temp_path = path.with_file_name(format!(".{}.metamusic-tmp", file_name));
+
+    // Copying also carries over the permission bits of the original
+    let result = fs::copy(path, &temp_path)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| Ok(tag.write_to_path(&temp_path, write_version(tag))?))
+        .and_then(|_| Ok(fs::rename(&temp_path, path)?));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Files keep the tag version they were read with, so v2.3 tags stay readable on players
+/// that never learned v2.4. Anything else is written as v2.4.
+fn write_version(tag: &Tag) -> Version {
+    match tag.version() {
+        Version::Id3v23 => Version::Id3v23,
+        _ => Version::Id3v24,
+    }
+}
+
+/// The rating frame written by metamusic, or else the first one another player left.
+fn popularimeter(tag: &Tag) -> Option<&Popularimeter> {
+    let ratings: Vec<&Popularimeter> = tag
+        .frames()
+        .filter_map(|frame| frame.content().popularimeter())
+        .collect();
+    ratings
+        .iter()
+        .find(|popm| popm.user == RATING_EMAIL)
+        .or(ratings.first())
+        .copied()
+}
+
+/// Star rating from 0 to 5, grouping the byte range the way players do.
+pub fn rating_stars(tag: &Tag) -> Option<u8> {
+    let stars = match popularimeter(tag)?.rating {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    };
+    Some(stars)
+}
+
+/// Parses a track number, optionally followed by the total: `3` or `3/12`.
+pub fn parse_track(value: &str) -> Result<(u32, Option<u32>), Box<dyn Error>> {
+    let invalid = || format!("invalid track '{}', expected 3 or 3/12", value.trim());
+    let (track, total) = match value.split_once('/') {
+        Some((track, total)) => (track, Some(total)),
+        None => (value, None),
+    };
+    let track = track.trim().parse().map_err(|_| invalid())?;
+    let total = match total {
+        Some(total) => Some(total.trim().parse().map_err(|_| invalid())?),
+        None => None,
+    };
+    Ok((track, total))
+}
+
+/// Parses `YYYY`, `YYYY-MM`, `YYYY-MM-DD` (optionally followed by `THH:MM:SS`) into a timestamp,
+/// rejecting values the ID3 parser would otherwise accept silently, such as month 13.
+pub fn parse_date(value: &str) -> Result<Timestamp, Box<dyn Error>> {
+    let value = value.trim();
+    let invalid = || format!("invalid date '{}', expected YYYY or YYYY-MM-DD", value);
+
+    let timestamp: Timestamp = value.parse().map_err(|_| invalid())?;
+    // The parser stops at the first thing it does not understand, so anything it
+    // did not consume shows up as a difference here
+    if timestamp.to_string() != value {
+        return Err(invalid().into());
+    }
+
+    if let Some(month) = timestamp.month {
+        if !(1..=12).contains(&month) {
+            return Err(format!("invalid month {} in '{}'", month, value).into());
+        }
+        if let Some(day) = timestamp.day {
+            let days_in_month = match month {
+                2 if timestamp.year % 4 == 0
+                    && (timestamp.year % 100 != 0 || timestamp.year % 400 == 0) =>
+                {
+                    29
+                }
+                2 => 28,
+                4 | 6 | 9 | 11 => 30,
+                _ => 31,
+            };
+            if day == 0 || day > days_in_month {
+                return Err(format!("invalid day {} in '{}'", day, value).into());
+            }
+        }
+    }
+
+    let time_valid = timestamp.hour.is_none_or(|hour| hour < 24)
+        && timestamp.minute.is_none_or(|minute| minute < 60)
+        && timestamp.second.is_none_or(|second| second < 60);
+    if !time_valid {
+        return Err(format!("invalid time in '{}'", value).into());
+    }
+
+    Ok(timestamp)
+}
+
+/// Path `file_path` would have after renaming it to `new_name` within its directory.
+pub fn renamed_path(file_path: &str, new_name: &str) -> Result<String, Box<dyn Error>> {
+    let new_name = new_name.trim();
+    if new_name.is_empty() || new_name.contains('/') || new_name == "." || new_name == ".." {
+        return Err(format!("invalid file name '{}'", new_name).into());
+    }
+
+    let target = Path::new(file_path).with_file_name(new_name);
+    target
+        .to_str()
+        .map(|target| target.to_string())
+        .ok_or_else(|| format!("invalid file name '{}'", new_name).into())
+}
+
+/// Whether renaming `old` to `target` would replace another file. A target that is
+/// `old` itself, as when only the case changes on a case-insensitive filesystem, is fine.
+pub fn rename_would_clobber(old: &str, target: &str) -> bool {
+    let target = Path::new(target);
+    // A link would be replaced rather than followed, even one leading back to `old`
+    if target.is_symlink() {
+        return true;
+    }
+    if !target.exists() {
+        return false;
+    }
+    match (fs::canonicalize(old), fs::canonicalize(target)) {
+        (Ok(old), Ok(target)) => old != target,
+        _ => true,
+    }
+}
+
+/// Makes `text` fit for a file name by `rules`.
+pub fn sanitize_filename(text: &str, rules: &FilenameRules) -> String {
+    let mut clean = String::new();
+    for c in text.chars() {
+        if rules.forbidden.contains(c) || c.is_control() {
+            clean.push_str(&rules.replacement);
+        } else {
+            clean.push(c);
+        }
+    }
+    if rules.collapse_whitespace {
+        clean = clean.split_whitespace().collect::<Vec<_>>().join(" ");
+    }
+    if rules.trim_trailing {
+        clean.truncate(clean.trim_end_matches(['.', ' ']).len());
+    }
+    clean
+}
+
+/// Builds the path `file_path` would get from a name template such as
+/// `{track} - {artist} - {title}`. Placeholders are filled from `tag`: `{title}`,
+/// `{artist}`, `{album}`, `{date}` and `{track}`, zero-padded to two digits, and
+/// cleaned up by `rules`. The original extension is kept.
+pub fn rename_from_template(
+    file_path: &str,
+    tag: &Tag,
+    template: &str,
+    rules: &FilenameRules,
+) -> Result<String, Box<dyn Error>> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in template '{}'", template))?;
+        let placeholder = &rest[start + 1..start + end];
+        let value = match placeholder {
+            "title" => field_value(tag, "Song Name"),
+            "artist" => field_value(tag, "Artist"),
+            "album" => field_value(tag, "Album"),
+            "date" => field_value(tag, "Date"),
+            "track" => tag
+                .track()
+                .map(|track| format!("{:02}", track))
+                .unwrap_or_default(),
+            _ => return Err(format!("unknown placeholder {{{}}}", placeholder).into()),
+        };
+        if value.trim().is_empty() {
+            return Err(format!("no {{{}}} in the tag", placeholder).into());
+        }
+        let value = sanitize_filename(&value, rules);
+        if value.contains('/') {
+            return Err(format!("{{{}}} contains '/'", placeholder).into());
+        }
+        name.push_str(value.trim());
+        rest = &rest[start + end + 1..];
+    }
+    name.push_str(rest);
+
+    if let Some(extension) = Path::new(file_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        name = format!("{}.{}", sanitize_filename(name.trim(), rules), extension);
+    } else {
+        name = sanitize_filename(&name, rules);
+    }
+    renamed_path(file_path, &name)
+}
+
+pub fn find_sidecar_art(file_path: &str) -> Option<PathBuf> {
+    let dir = match Path::new(file_path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    SIDECAR_ART_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// The cover `<art_dir>/<album>.jpg` (or `.jpeg`/`.png`) for the album of `file`, if
+/// there is one.
+pub fn album_cover(file: &str, art_dir: &Path) -> Option<PathBuf> {
+    let album = Tag::read_from_path(file)
+        .ok()
+        .and_then(|tag| tag.album().map(|album| album.to_string()))
+        .filter(|album| !album.is_empty() && !album.contains('/'))?;
+    ["jpg", "jpeg", "png"]
+        .iter()
+        .map(|ext| art_dir.join(format!("{}.{}", album, ext)))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Removes the ID3v2 tag and any ID3v1 tag from the file, leaving only the audio.
+/// Returns whether there was anything to remove. `expected` is checked as by
+/// `modify_field`.
+pub fn strip_all_tags(
+    file_path: &str,
+    expected: Option<SystemTime>,
+) -> Result<bool, Box<dyn Error>> {
+    check_unchanged(file_path, expected)?;
+    let v2 = Tag::remove_from_path(file_path)?;
+    let v1 = id3::v1::Tag::remove_from_path(file_path)?;
+    Ok(v2 || v1)
+}
+
+/// Removes every picture frame from `file_path`, unless it has changed on disk since
+/// `expected` as with `modify_field`. Returns the space saved on disk, or `None` when
+/// there was no picture to remove.
+pub fn remove_art(
+    file_path: &str,
+    expected: Option<SystemTime>,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    check_unchanged(file_path, expected)?;
+    let size = |path: &str| fs::metadata(path).map(|metadata| metadata.len());
+    let before = size(file_path)?;
+    let mut tag = read_tag_for_write(file_path)?;
+    if tag.pictures().next().is_none() {
+        return Ok(None);
+    }
+    tag.remove_all_pictures();
+    write_tag(&tag, file_path)?;
+    Ok(Some(
+        before.saturating_sub(size(file_path).unwrap_or(before)),
+    ))
+}
+
+pub fn mime_for_image(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("bmp") => "image/bmp",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+/// Longest side a pasted cover is scaled down to: a screenshot can be far larger than
+/// any player shows a cover, and a PNG of it would bloat every copy of the file.
+const PASTED_COVER_MAX_SIDE: u32 = 1500;
+
+/// A PNG ready to embed, with the size it was encoded at.
+pub struct EncodedPng {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Encodes raw RGBA pixels, as the clipboard hands them out, into a PNG to embed,
+/// scaled down to fit `PASTED_COVER_MAX_SIDE`.
+pub fn encode_png(width: u32, height: u32, rgba: Vec<u8>) -> Result<EncodedPng, Box<dyn Error>> {
+    if width == 0 || height == 0 {
+        return Err("the clipboard image is empty".into());
+    }
+    let mut image = image::DynamicImage::from(
+        image::RgbaImage::from_raw(width, height, rgba)
+            .ok_or("the clipboard image is malformed")?,
+    );
+    if width.max(height) > PASTED_COVER_MAX_SIDE {
+        image = image.thumbnail(PASTED_COVER_MAX_SIDE, PASTED_COVER_MAX_SIDE);
+    }
+    let mut data = Vec::new();
+    image.write_to(
+        &mut std::io::Cursor::new(&mut data),
+        image::ImageFormat::Png,
+    )?;
+    Ok(EncodedPng {
+        data,
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// Picture types offered when re-tagging a picture, in the order they are cycled through.
+pub const PICTURE_TYPES: &[PictureType] = &[
+    PictureType::CoverFront,
+    PictureType::CoverBack,
+    PictureType::Leaflet,
+    PictureType::Media,
+    PictureType::LeadArtist,
+    PictureType::Artist,
+    PictureType::Band,
+    PictureType::Illustration,
+    PictureType::Other,
+];
+
+/// The embedded pictures of `tag` in order, taken out so they can be rewritten.
+fn take_pictures(tag: &mut Tag) -> Vec<Picture> {
+    let pictures: Vec<Picture> = tag.pictures().cloned().collect();
+    tag.remove_all_pictures();
+    pictures
+}
+
+/// Puts `pictures` back in order. `add_frame` would let a picture replace an earlier
+/// one of the same type, so they are appended as they are.
+fn put_pictures(tag: &mut Tag, pictures: Vec<Picture>) {
+    tag.extend(pictures.into_iter().map(Frame::from));
+}
+
+/// Brings the next embedded picture to the front, where players look first, and makes
+/// it the front cover. The old front cover takes over the type it had. Returns the new
+/// types in order. `expected` is checked as by `modify_field`.
+pub fn next_picture(
+    file_path: &str,
+    expected: Option<SystemTime>,
+) -> Result<Vec<PictureType>, Box<dyn Error>> {
+    check_unchanged(file_path, expected)?;
+    let mut tag = read_tag_for_write(file_path)?;
+    let types = rotate_pictures(&mut tag)?;
+    write_tag(&tag, file_path)?;
+    Ok(types)
+}
+
+fn rotate_pictures(tag: &mut Tag) -> Result<Vec<PictureType>, Box<dyn Error>> {
+    let mut pictures = take_pictures(tag);
+    if pictures.len() < 2 {
+        put_pictures(tag, pictures);
+        return Err("there is no other picture to bring forward".into());
+    }
+
+    pictures.rotate_left(1);
+    let promoted = pictures[0].picture_type;
+    if let Some(front) = pictures
+        .iter_mut()
+        .find(|picture| picture.picture_type == PictureType::CoverFront)
+    {
+        front.picture_type = promoted;
+    }
+    pictures[0].picture_type = PictureType::CoverFront;
+
+    let types = pictures
+        .iter()
+        .map(|picture| picture.picture_type)
+        .collect();
+    put_pictures(tag, pictures);
+    Ok(types)
+}
+
+/// Gives the first embedded picture the next type of `PICTURE_TYPES`, swapping types
+/// with the picture that already has it. `expected` is checked as by `modify_field`.
+pub fn cycle_picture_type(
+    file_path: &str,
+    expected: Option<SystemTime>,
+) -> Result<PictureType, Box<dyn Error>> {
+    check_unchanged(file_path, expected)?;
+    let mut tag = read_tag_for_write(file_path)?;
+    let new = cycle_first_picture(&mut tag)?;
+    write_tag(&tag, file_path)?;
+    Ok(new)
+}
+
+fn cycle_first_picture(tag: &mut Tag) -> Result<PictureType, Box<dyn Error>> {
+    let mut pictures = take_pictures(tag);
+    let Some(first) = pictures.first() else {
+        return Err("no embedded picture".into());
+    };
+
+    let old = first.picture_type;
+    let new = PICTURE_TYPES
+        .iter()
+        .position(|picture_type| *picture_type == old)
+        .map_or(PICTURE_TYPES[0], |index| {
+            PICTURE_TYPES[(index + 1) % PICTURE_TYPES.len()]
+        });
+    if let Some(holder) = pictures
+        .iter_mut()
+        .find(|picture| picture.picture_type == new)
+    {
+        holder.picture_type = old;
+    }
+    pictures[0].picture_type = new;
+
+    put_pictures(tag, pictures);
+    Ok(new)
+}
+
+/// Embeds `data` as the front cover of `file_path`, replacing the one it has, unless
+/// the file has changed on disk since `expected` as with `modify_field`.
+pub fn embed_art(
+    file_path: &str,
+    data: Vec<u8>,
+    mime_type: &str,
+    expected: Option<SystemTime>,
+) -> Result<(), Box<dyn Error>> {
+    check_unchanged(file_path, expected)?;
+    let mut tag = read_tag_for_write(file_path)?;
+
+    tag.remove_picture_by_type(PictureType::CoverFront);
+    tag.add_frame(Picture {
+        mime_type: mime_type.to_string(),
+        picture_type: PictureType::CoverFront,
+        description: String::new(),
+        data,
+    });
+
+    write_tag(&tag, file_path)
+}
+
+/// Candidates for completing the last component of `partial`, directories suffixed with `/`.
+pub fn complete_path(partial: &str) -> Vec<String> {
+    let (dir_part, prefix) = match partial.rfind('/') {
+        Some(idx) => partial.split_at(idx + 1),
+        None => ("", partial),
+    };
+    let dir = if dir_part.is_empty() { "." } else { dir_part };
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut matches: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            // Hidden entries only show up once the user starts typing them
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir_part, name, suffix))
+        })
+        .collect();
+
+    matches.sort();
+    matches
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Health summary of the listed files.
+pub struct LibraryStats {
+    pub files: usize,
+    /// Files whose tag could not be parsed.
+    pub unreadable: usize,
+    /// How many readable files lack each preview field, by label.
+    pub missing: Vec<(String, usize)>,
+    pub without_art: usize,
+    pub artists: usize,
+    pub albums: usize,
+    /// The total playing time, `None` while `total_duration` is still measuring it.
+    pub duration: Option<Duration>,
+    /// Files whose playing time could not be worked out.
+    pub unknown_duration: usize,
+}
+
+/// Sums up `tags`, the cached tags of each of `files` in order, leaving the playing
+/// time to `total_duration`.
+pub fn compute_stats(files: &[String], tags: &[Option<TagInfo>]) -> LibraryStats {
+    let mut missing: Vec<(String, usize)> = Vec::new();
+    let mut artists = HashSet::new();
+    let mut albums = HashSet::new();
+    let mut without_art = 0;
+    let mut unreadable = 0;
+
+    for tag_info in tags {
+        let Some(tag_info) = tag_info else {
+            unreadable += 1;
+            continue;
+        };
+        for (label, _, value) in &tag_info.preview {
+            let index = match missing.iter().position(|(name, _)| name == label) {
+                Some(index) => index,
+                None => {
+                    missing.push((label.clone(), 0));
+                    missing.len() - 1
+                }
+            };
+            if value.trim().is_empty() {
+                missing[index].1 += 1;
+            }
+        }
+        if !tag_info.artist.trim().is_empty() {
+            artists.insert(tag_info.artist.trim().to_lowercase());
+        }
+        if !tag_info.album.trim().is_empty() {
+            albums.insert(tag_info.album.trim().to_lowercase());
+        }
+        if !tag_info.has_art {
+            without_art += 1;
+        }
+    }
+
+    LibraryStats {
+        files: files.len(),
+        unreadable,
+        missing,
+        without_art,
+        artists: artists.len(),
+        albums: albums.len(),
+        duration: None,
+        unknown_duration: 0,
+    }
+}
+
+/// The playing time of `files` together, with how many of them it could not be worked
+/// out for. Every file is read, so this is slow on a large library.
+pub fn total_duration(files: &[String]) -> (Duration, usize) {
+    let mut duration = Duration::ZERO;
+    let mut unknown = 0;
+    for file in files {
+        match mp3_duration(Path::new(file)) {
+            Some(length) => duration += length,
+            None => unknown += 1,
+        }
+    }
+    (duration, unknown)
+}
+
+/// How much an `Issue` matters, most serious first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Notice,
+}
+
+impl Severity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "Errors",
+            Severity::Warning => "Warnings",
+            Severity::Notice => "Notices",
+        }
+    }
+}
+
+/// A problem `verify_library` found with a file, or with a folder as a whole.
+pub struct Issue {
+    pub severity: Severity,
+    pub file: String,
+    pub problem: String,
+}
+
+/// Audits `tags`, the cached tags of each of `files` in order, for the usual problems
+/// of a collection: unreadable tags, missing titles, artists, albums and art, track
+/// numbers and dates no player will understand, text that looks wrongly decoded, and
+/// album folders that disagree on the album artist. Issues come most serious first.
+pub fn verify_library(files: &[String], tags: &[Option<TagInfo>]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |severity, file: &str, problem: String| {
+        issues.push(Issue {
+            severity,
+            file: file.to_string(),
+            problem,
+        })
+    };
+    for (file, tag_info) in files.iter().zip(tags) {
+        let Some(tag_info) = tag_info else {
+            issue(Severity::Error, file, "the tag cannot be read".to_string());
+            continue;
+        };
+        for (field, value) in [
+            ("title", &tag_info.title),
+            ("artist", &tag_info.artist),
+            ("album", &tag_info.album),
+        ] {
+            if value.trim().is_empty() {
+                issue(Severity::Warning, file, format!("no {}", field));
+            }
+        }
+        if !tag_info.has_art {
+            issue(Severity::Notice, file, "no cover art".to_string());
+        }
+    }
+
+    // The rest needs the frames as stored, not as the preview shows them
+    for (folder, tracks) in tracks_by_folder(files) {
+        let mut album_artists: BTreeMap<String, usize> = BTreeMap::new();
+        for (file, tag) in &tracks {
+            let text = |id: &str| tag.get(id).and_then(|frame| frame.content().text());
+            if let Some(track) = text("TRCK")
+                && parse_track(track).is_err()
+            {
+                issue(
+                    Severity::Error,
+                    file,
+                    format!("track '{}' is not a number", track),
+                );
+            }
+            for id in ["TDRC", "TYER"] {
+                if let Some(date) = text(id)
+                    && parse_date(date).is_err()
+                {
+                    issue(
+                        Severity::Error,
+                        file,
+                        format!("{} '{}' is not a date", id, date),
+                    );
+                }
+            }
+            for frame in tag.frames() {
+                if let Some(value) = frame.content().text()
+                    && looks_misdecoded(value)
+                {
+                    issue(
+                        Severity::Warning,
+                        file,
+                        format!("{} '{}' looks wrongly encoded", frame.id(), value),
+                    );
+                }
+            }
+            if let Some(album_artist) = text("TPE2").filter(|value| !value.trim().is_empty()) {
+                *album_artists
+                    .entry(album_artist.trim().to_string())
+                    .or_default() += 1;
+            }
+        }
+
+        if album_artists.len() > 1 {
+            let artists: Vec<String> = album_artists
+                .iter()
+                .map(|(artist, count)| format!("'{}' ×{}", artist, count))
+                .collect();
+            issue(
+                Severity::Warning,
+                &format!("{}/", folder),
+                format!("album artists differ: {}", artists.join(", ")),
+            );
+        }
+    }
+
+    issues.sort_by(|a, b| {
+        a.severity
+            .cmp(&b.severity)
+            .then_with(|| a.file.cmp(&b.file))
+    });
+    issues
+}
+
+/// The readable tags of `files`, grouped by the folder each file is in.
+fn tracks_by_folder(files: &[String]) -> BTreeMap<String, Vec<(&String, Tag)>> {
+    let mut folders: BTreeMap<String, Vec<(&String, Tag)>> = BTreeMap::new();
+    for file in files {
+        let Ok(tag) = Tag::read_from_path(file) else {
+            continue;
+        };
+        let folder = Path::new(file)
+            .parent()
+            .map(|folder| folder.display().to_string())
+            .unwrap_or_default();
+        folders.entry(folder).or_default().push((file, tag));
+    }
+
+    folders
+}
+
+/// Fields every track of an album folder is expected to share.
+pub const ALBUM_FIELDS: &[&str] = &["Album", "Album Artist", "Date", "Genre"];
+
+/// A field of `ALBUM_FIELDS` the tracks of one folder disagree on.
+pub struct AlbumConflict {
+    pub folder: String,
+    pub field: &'static str,
+    /// Each value with the files that have it, most common first. An empty value
+    /// stands for the files without the field.
+    pub values: Vec<(String, Vec<String>)>,
+}
+
+impl AlbumConflict {
+    /// The value to give every track: the most common one that is not empty.
+    pub fn majority(&self) -> &str {
+        self.values
+            .iter()
+            .map(|(value, _)| value.as_str())
+            .find(|value| !value.is_empty())
+            .unwrap_or("")
+    }
+}
+
+/// The fields of `ALBUM_FIELDS` that vary between the tracks of a folder, over the
+/// folders of `files` with more than one readable track.
+pub fn album_conflicts(files: &[String]) -> Vec<AlbumConflict> {
+    let mut conflicts = Vec::new();
+    for (folder, tracks) in tracks_by_folder(files) {
+        if tracks.len() < 2 {
+            continue;
+        }
+        for field in ALBUM_FIELDS {
+            let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for (file, tag) in &tracks {
+                let value = field_value(tag, field).trim().to_string();
+                values.entry(value).or_default().push(file.to_string());
+            }
+            if values.len() < 2 {
+                continue;
+            }
+            let mut values: Vec<_> = values.into_iter().collect();
+            // Stable, so equally common values stay in alphabetical order
+            values.sort_by_key(|(_, files)| std::cmp::Reverse(files.len()));
+            conflicts.push(AlbumConflict {
+                folder: folder.clone(),
+                field,
+                values,
+            });
+        }
+    }
+    conflicts
+}
+
+/// Album artist given to the tracks of a compilation.
+pub const VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// A folder that looks like a various-artists compilation: one album, many artists.
+pub struct Compilation {
+    pub folder: String,
+    pub album: String,
+    /// Each artist with how many tracks they have, most first.
+    pub artists: Vec<(String, usize)>,
+    pub files: Vec<String>,
+}
+
+/// The folders of `files` whose tracks, at least three, all share one album but have
+/// three or more artists, leaving out those already tagged as a compilation.
+pub fn detect_compilations(files: &[String]) -> Vec<Compilation> {
+    let mut compilations = Vec::new();
+    for (folder, tracks) in tracks_by_folder(files) {
+        let albums: HashSet<&str> = tracks
+            .iter()
+            .map(|(_, tag)| tag.album().unwrap_or("").trim())
+            .collect();
+        let [album] = albums.into_iter().collect::<Vec<_>>()[..] else {
+            continue;
+        };
+        if tracks.len() < 3 || album.is_empty() {
+            continue;
+        }
+        let tagged = tracks.iter().all(|(_, tag)| {
+            field_value(tag, "Album Artist") == VARIOUS_ARTISTS
+                && tag.get("TCMP").and_then(|frame| frame.content().text()) == Some("1")
+        });
+        if tagged {
+            continue;
+        }
+
+        // Tracks without an artist say nothing about how many there are
+        let mut artists: BTreeMap<String, usize> = BTreeMap::new();
+        for (_, tag) in &tracks {
+            let artist = tag.artist().unwrap_or("").trim();
+            if !artist.is_empty() {
+                *artists.entry(artist.to_string()).or_default() += 1;
+            }
+        }
+        if artists.len() < 3 {
+            continue;
+        }
+        let mut artists: Vec<_> = artists.into_iter().collect();
+        artists.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        compilations.push(Compilation {
+            folder,
+            album: album.to_string(),
+            artists,
+            files: tracks.iter().map(|(file, _)| file.to_string()).collect(),
+        });
+    }
+    compilations
+}
+
+/// Tags `file` as part of a compilation: album artist "Various Artists" and the
+/// TCMP flag players group compilations by. `expected` is checked as by `modify_field`.
+pub fn mark_compilation(file: &str, expected: Option<SystemTime>) -> Result<(), Box<dyn Error>> {
+    check_unchanged(file, expected)?;
+    let mut tag = read_tag_for_write(file)?;
+    tag.set_text("TPE2", VARIOUS_ARTISTS);
+    tag.set_text("TCMP", "1");
+    write_tag(&tag, file)
+}
+
+/// Whether `text` carries the usual marks of a bad decode: replacement characters,
+/// control characters, or UTF-8 read as Latin-1, which turns "é" into "Ã©".
+fn looks_misdecoded(text: &str) -> bool {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{FFFD}' || c.is_control() && c != '\0' && c != '\n' {
+            return true;
+        }
+        if matches!(c, 'Ã' | 'Â')
+            && chars
+                .peek()
+                .is_some_and(|next| ('\u{80}'..='\u{BF}').contains(next))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// `2h 05m`, or `3m 07s` for less than an hour.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+    if hours > 0 {
+        format!("{}h {:02}m", hours, minutes)
+    } else {
+        format!("{}m {:02}s", minutes, seconds % 60)
+    }
+}
+
+/// Relative age for the last week ("2h ago"), the local date after that.
+pub fn format_modified(modified: SystemTime) -> String {
+    let Ok(age) = SystemTime::now().duration_since(modified) else {
+        return DateTime::<Local>::from(modified)
+            .format("%Y-%m-%d %H:%M")
+            .to_string();
+    };
+
+    match age.as_secs() {
+        0..60 => "just now".to_string(),
+        secs @ 60..3600 => format!("{}m ago", secs / 60),
+        secs @ 3600..86400 => format!("{}h ago", secs / 3600),
+        secs @ 86400..604800 => format!("{}d ago", secs / 86400),
+        _ => DateTime::<Local>::from(modified)
+            .format("%Y-%m-%d")
+            .to_string(),
+    }
+}
+
+/// A configured transform, checked and with its pattern compiled.
+pub struct TransformRule {
+    pub name: String,
+    pub field: &'static str,
+    pattern: Regex,
+    replace: String,
+}
+
+impl TransformRule {
+    pub fn compile(transform: &Transform) -> Result<Self, Box<dyn Error>> {
+        let field = find_field(&transform.field).ok_or_else(|| {
+            format!(
+                "transform '{}': unknown field '{}'",
+                transform.name, transform.field
+            )
+        })?;
+        let pattern = Regex::new(&transform.find)
+            .map_err(|e| format!("transform '{}': {}", transform.name, e))?;
+        Ok(TransformRule {
+            name: transform.name.clone(),
+            field,
+            pattern,
+            replace: transform.replace.clone(),
+        })
+    }
+}
+
+/// `value` with every match of the rule replaced. A value that matched is trimmed, so
+/// a rule deleting a trailing "(Live)" need not also match the space before it; one
+/// that did not is left exactly as it was.
+pub fn apply_transform(value: &str, rule: &TransformRule) -> String {
+    if !rule.pattern.is_match(value) {
+        return value.to_string();
+    }
+    rule.pattern
+        .replace_all(value, rule.replace.as_str())
+        .trim()
+        .to_string()
+}
+
+pub struct FindReplace {
+    pub field: String,
+    pub search: String,
+    pub replacement: String,
+    pub case_insensitive: bool,
+    pub whole_field: bool,
+}
+
+impl FindReplace {
+    /// The replaced value, or `None` when `value` does not match.
+    pub fn apply(&self, value: &str) -> Option<String> {
+        if self.search.is_empty() {
+            return None;
+        }
+
+        if self.whole_field {
+            let matches = if self.case_insensitive {
+                value.to_lowercase() == self.search.to_lowercase()
+            } else {
+                value == self.search
+            };
+            return matches.then(|| self.replacement.clone());
+        }
+
+        let mut result = String::new();
+        let mut rest = value;
+        let mut replaced = false;
+        while let Some((start, end)) = self.find(rest) {
+            result.push_str(&rest[..start]);
+            result.push_str(&self.replacement);
+            rest = &rest[end..];
+            replaced = true;
+        }
+        result.push_str(rest);
+
+        replaced.then_some(result)
+    }
+
+    /// Byte range of the first occurrence of the search string in `haystack`.
+    fn find(&self, haystack: &str) -> Option<(usize, usize)> {
+        if !self.case_insensitive {
+            return haystack
+                .find(&self.search)
+                .map(|start| (start, start + self.search.len()));
+        }
+
+        // Lowercasing can change byte lengths, so compare char by char from each boundary
+        let needle = self.search.to_lowercase();
+        for (start, _) in haystack.char_indices() {
+            let mut candidate = String::new();
+            for (offset, c) in haystack[start..].char_indices() {
+                candidate.extend(c.to_lowercase());
+                if candidate == needle {
+                    return Some((start, start + offset + c.len_utf8()));
+                }
+                if !needle.starts_with(&candidate) {
+                    break;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Metadata implied by a file name such as `03 - Artist - Title.mp3`.
+#[derive(Default)]
+pub struct FilenameTags {
+    pub track: Option<u32>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Recognises `NN - Artist - Title`, `NN - Title`, `Artist - Title` and `NN. Title` names.
+/// Names without any of that structure yield nothing rather than a guessed title.
+pub fn parse_filename(file_path: &str) -> FilenameTags {
+    let Some(stem) = Path::new(file_path).file_stem().and_then(|s| s.to_str()) else {
+        return FilenameTags::default();
+    };
+
+    let parts: Vec<&str> = stem.split(" - ").map(str::trim).collect();
+    let leading_track = |part: &str| part.parse::<u32>().ok();
+
+    match parts.as_slice() {
+        [track, artist, title @ ..] if leading_track(track).is_some() && !title.is_empty() => {
+            FilenameTags {
+                track: leading_track(track),
+                artist: Some(artist.to_string()),
+                title: Some(title.join(" - ")),
+            }
+        }
+        [track, title] if leading_track(track).is_some() => FilenameTags {
+            track: leading_track(track),
+            artist: None,
+            title: Some(title.to_string()),
+        },
+        [artist, title @ ..] if !title.is_empty() => FilenameTags {
+            track: None,
+            artist: Some(artist.to_string()),
+            title: Some(title.join(" - ")),
+        },
+        _ => {
+            // "03. Title", "03_Title", "03 Title"
+            let digits: String = stem.chars().take_while(|c| c.is_ascii_digit()).collect();
+            let rest = stem[digits.len()..].trim_start_matches(['.', '_', '-', ' ']);
+            if digits.is_empty() || rest.is_empty() || rest.len() == stem.len() - digits.len() {
+                return FilenameTags::default();
+            }
+            FilenameTags {
+                track: digits.parse().ok(),
+                artist: None,
+                title: Some(rest.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picture(picture_type: PictureType, data: u8) -> Picture {
+        Picture {
+            mime_type: "image/png".to_string(),
+            picture_type,
+            description: String::new(),
+            data: vec![data],
+        }
+    }
+
+    /// A fresh, empty directory for the test called `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("metamusic-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes about a second of silent MP3 audio, 128 kbit/s at 44.1 kHz, without any
+    /// tag, and returns its bytes.
+    fn write_silence(path: &Path) -> Vec<u8> {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        let audio = frame.repeat(38);
+        fs::write(path, &audio).unwrap();
+        audio
+    }
+
+    /// The data byte of each picture of `tag`, in order.
+    fn picture_data(tag: &Tag) -> Vec<u8> {
+        tag.pictures().map(|picture| picture.data[0]).collect()
+    }
+
+    #[test]
+    fn rotating_keeps_pictures_of_the_same_type() {
+        let mut tag: Tag = [
+            picture(PictureType::CoverFront, 1),
+            picture(PictureType::Other, 2),
+            picture(PictureType::Other, 3),
+        ]
+        .into_iter()
+        .map(Frame::from)
+        .collect();
+
+        let types = rotate_pictures(&mut tag).unwrap();
+        assert_eq!(
+            types,
+            [
+                PictureType::CoverFront,
+                PictureType::Other,
+                PictureType::Other
+            ]
+        );
+        assert_eq!(picture_data(&tag), [2, 3, 1]);
+
+        cycle_first_picture(&mut tag).unwrap();
+        assert_eq!(picture_data(&tag), [2, 3, 1]);
+    }
+
+    #[test]
+    fn pasted_images_are_scaled_to_cover_size() {
+        let png = encode_png(3000, 1000, vec![0; 3000 * 1000 * 4]).unwrap();
+        assert_eq!((png.width, png.height), (PASTED_COVER_MAX_SIDE, 500));
+
+        let png = encode_png(300, 200, vec![0; 300 * 200 * 4]).unwrap();
+        assert_eq!((png.width, png.height), (300, 200));
+        assert!(encode_png(0, 0, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn failed_write_leaves_the_original_intact() {
+        let dir = temp_dir("failed-write");
+        let file = dir.join("track.mp3");
+        let audio = write_silence(&file);
+        // A directory in the way of the temporary copy makes the first step fail
+        fs::create_dir(dir.join(".track.mp3.metamusic-tmp")).unwrap();
+
+        let mut tag = Tag::new();
+        tag.set_title("Title");
+        assert!(write_tag(&tag, file.to_str().unwrap()).is_err());
+        assert_eq!(fs::read(&file).unwrap(), audio);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stripped_file_keeps_its_audio() {
+        let dir = temp_dir("strip");
+        let file = dir.join("track.mp3");
+        let audio = write_silence(&file);
+        let path = file.to_str().unwrap();
+        let mut tag = Tag::new();
+        tag.set_title("Title");
+        write_tag(&tag, path).unwrap();
+        // An ID3v1 tag is the last 128 bytes, starting with "TAG"
+        let mut v1 = b"TAG".to_vec();
+        v1.resize(128, 0);
+        let mut data = fs::read(&file).unwrap();
+        data.extend(v1);
+        fs::write(&file, data).unwrap();
+
+        assert!(strip_all_tags(path, None).unwrap());
+        assert_eq!(fs::read(&file).unwrap(), audio);
+        assert!(is_mp3_content(&file));
+        assert!(mp3_duration(&file).is_some_and(|duration| duration.as_secs_f64() > 0.9));
+        // With a decoder built in, check the frames play back rather than only that the
+        // bytes are unchanged
+        #[cfg(any(feature = "replaygain", feature = "waveform"))]
+        {
+            let mut samples = 0;
+            crate::decode::decode(path, |channels, _, buffer| {
+                samples += buffer.len() / channels as usize;
+                Ok(())
+            })
+            .unwrap();
+            assert!(samples > 40_000);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_files_are_listed_and_written_through() {
+        let dir = temp_dir("symlink");
+        let (music, links) = (dir.join("music"), dir.join("links"));
+        fs::create_dir_all(&music).unwrap();
+        fs::create_dir_all(&links).unwrap();
+        let real = music.join("track.mp3");
+        write_silence(&real);
+        let link = links.join("linked.mp3");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let extensions = ["mp3".to_string()];
+
+        let link_path = link.to_str().unwrap().to_string();
+        let scan = scan_roots(&[links.to_str().unwrap().to_string()], &extensions, false);
+        assert_eq!(scan.files, vec![link_path.clone()]);
+        // Reached through two roots, the file is still listed once
+        let roots = [
+            music.to_str().unwrap().to_string(),
+            links.to_str().unwrap().to_string(),
+        ];
+        assert_eq!(scan_roots(&roots, &extensions, false).files.len(), 1);
+
+        modify_field(&link_path, "Song Name", "Through the link", None).unwrap();
+        assert!(link.is_symlink());
+        let tag = Tag::read_from_path(&real).unwrap();
+        assert_eq!(tag.title(), Some("Through the link"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn renames_onto_other_files_would_clobber() {
+        let dir = temp_dir("clobber");
+        let old = dir.join("a.mp3");
+        let other = dir.join("b.mp3");
+        write_silence(&old);
+        write_silence(&other);
+        let (old, other) = (old.to_str().unwrap(), other.to_str().unwrap());
+
+        assert!(rename_would_clobber(old, other));
+        assert!(!rename_would_clobber(old, old));
+        assert!(!rename_would_clobber(
+            old,
+            dir.join("c.mp3").to_str().unwrap()
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn v23_dates_round_trip_through_tyer_and_tdat() {
+        let dir = temp_dir("v23-date");
+        let file = dir.join("track.mp3");
+        write_silence(&file);
+        let path = file.to_str().unwrap();
+        let mut tag = Tag::with_version(Version::Id3v23);
+        tag.set_title("Title");
+        write_tag(&tag, path).unwrap();
+
+        modify_field(path, "Date", "2001-05-07", None).unwrap();
+        let tag = Tag::read_from_path(path).unwrap();
+        let text = |id: &str| tag.get(id).and_then(|frame| frame.content().text());
+        assert_eq!(tag.version(), Version::Id3v23);
+        assert_eq!(text("TYER"), Some("2001"));
+        assert_eq!(text("TDAT"), Some("0705"));
+        assert_eq!(text("TDRC"), None);
+        assert_eq!(field_value(&tag, "Date"), "2001-05-07");
+
+        modify_field(path, "Date", "2001-05-07T10:30", None).unwrap();
+        let tag = Tag::read_from_path(path).unwrap();
+        assert_eq!(field_value(&tag, "Date"), "2001-05-07T10:30");
+
+        modify_field(path, "Date", "1999", None).unwrap();
+        let tag = Tag::read_from_path(path).unwrap();
+        assert_eq!(tag.get("TDAT"), None);
+        assert_eq!(field_value(&tag, "Date"), "1999");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn edits_keep_frames_the_editor_does_not_know() {
+        use id3::frame::{Content, Private, Unknown};
+
+        let dir = temp_dir("exotic");
+        let file = dir.join("track.mp3");
+        write_silence(&file);
+        let path = file.to_str().unwrap();
+        let mut tag = Tag::new();
+        tag.add_frame(ExtendedText {
+            description: "MusicBrainz Album Id".to_string(),
+            value: "1234".to_string(),
+        });
+        tag.add_frame(Private {
+            owner_identifier: "example.com".to_string(),
+            private_data: vec![0, 1, 2, 255],
+        });
+        tag.add_frame(Frame::with_content(
+            "XABC",
+            Content::Unknown(Unknown {
+                data: vec![7, 0, 7],
+                version: Version::Id3v24,
+            }),
+        ));
+        write_tag(&tag, path).unwrap();
+
+        modify_field(path, "Artist", "Artist", None).unwrap();
+        let edited = Tag::read_from_path(path).unwrap();
+        assert_eq!(edited.artist(), Some("Artist"));
+        for frame in tag.frames() {
+            let kept = edited.get(frame.id()).map(|kept| kept.content());
+            assert_eq!(kept, Some(frame.content()), "{} was lost", frame.id());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extensions_match_whatever_their_case() {
+        let dir = temp_dir("extensions");
+        for name in ["a.mp3", "b.MP3", "c.Mp3", "d.txt", "e.mp3x"] {
+            write_silence(&dir.join(name));
+        }
+
+        let scan = get_music_files(dir.to_str().unwrap(), &["mp3".to_string()], false);
+        assert_eq!(scan.files, ["a.mp3", "b.MP3", "c.Mp3"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tracks_without_an_artist_do_not_make_a_compilation() {
+        let dir = temp_dir("compilation");
+        let mut files = Vec::new();
+        for (name, artist) in [("1.mp3", "A"), ("2.mp3", "B"), ("3.mp3", ""), ("4.mp3", "")] {
+            let file = dir.join(name);
+            write_silence(&file);
+            let path = file.to_str().unwrap().to_string();
+            let mut tag = Tag::new();
+            tag.set_album("Album");
+            if !artist.is_empty() {
+                tag.set_artist(artist);
+            }
+            write_tag(&tag, &path).unwrap();
+            files.push(path);
+        }
+        assert!(detect_compilations(&files).is_empty());
+
+        modify_field(&files[2], "Artist", "C", None).unwrap();
+        let compilations = detect_compilations(&files);
+        assert_eq!(compilations.len(), 1);
+        assert_eq!(compilations[0].artists.len(), 3);
+        assert_eq!(compilations[0].files.len(), 4);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn transforms_only_trim_values_they_change() {
+        let rule = TransformRule {
+            name: "No live".to_string(),
+            field: "Song Name",
+            pattern: Regex::new(r"\(Live\)").unwrap(),
+            replace: String::new(),
+        };
+        assert_eq!(apply_transform("Song (Live)", &rule), "Song");
+        assert_eq!(apply_transform(" Song ", &rule), " Song ");
+    }
 }
@@ -1,42 +1,335 @@
 mod app;
+mod config;
+#[cfg(any(feature = "replaygain", feature = "waveform"))]
+mod decode;
 mod functions;
+#[cfg(feature = "replaygain")]
+mod replaygain;
 mod ui;
+#[cfg(feature = "waveform")]
+mod waveform;
 
+use crate::config::Config;
+use crate::functions::{
+    field_input, find_field, modify_field, read_tag_info, scan_roots, verify_library, Severity,
+};
 use crate::ui::*;
 use app::*;
 
-use crossterm::event::{self, Event, KeyCode};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::error::Error;
 use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: metamusic [OPTIONS] [PATH]...
+       metamusic set --field FIELD --value VALUE FILE...
+       metamusic verify [PATH]...
+
+Edit the tags of the music files in each directory PATH (the current directory by
+default). A file PATH opens the directory it is in, with that file selected.
+
+The set command writes VALUE to FIELD (such as Artist, Title or Track) of every
+FILE without starting the interface, and fails if any file could not be written.
+
+The verify command checks the files in each PATH, or the files given, for common
+tagging problems and fails if any of them is an error.
+
+Options:
+  -p, --probe    Also list files holding MP3 audio under another extension
+  -h, --help     Print this help and exit
+  -V, --version  Print the version and exit";
+
+/// `metamusic set`: writes one field of every file given and reports each one.
+fn run_set(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut field = None;
+    let mut value = None;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--field" => field = args.next(),
+            "--value" => value = args.next(),
+            flag if flag.starts_with("--") => {
+                return Err(format!("unknown option '{}', see --help", flag).into());
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    let (Some(field), Some(value)) = (field, value) else {
+        return Err("set needs --field and --value, see --help".into());
+    };
+    let field = find_field(field).ok_or_else(|| format!("unknown field '{}'", field))?;
+    if let Some(input) = field_input(field)
+        && !(input.is_valid)(value)
+    {
+        return Err(format!("invalid {} '{}', expected {}", field, value, input.hint).into());
+    }
+    if files.is_empty() {
+        return Err("set needs at least one file".into());
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        match modify_field(file, field, value, None) {
+            Ok(_) => println!("✓ {}", file),
+            Err(e) => {
+                eprintln!("✗ {}: {}", file, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} files could not be written", failed, files.len()).into());
+    }
+    Ok(())
+}
+
+/// `metamusic verify`: the library check of the interface, printed by severity.
+fn run_verify(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let config = Config::load()?;
+    let (mut files, roots): (Vec<String>, Vec<String>) = args
+        .iter()
+        .cloned()
+        .partition(|path| Path::new(path).is_file());
+    if files.is_empty() || !roots.is_empty() {
+        let scan = scan_roots(&roots, &config.extensions, false);
+        for warning in &scan.warnings {
+            eprintln!("✗ skipped {}", warning);
+        }
+        files.extend(scan.files);
+    }
+
+    let tags: Vec<_> = files
+        .iter()
+        .map(|file| read_tag_info(file, &config.preview_fields).ok())
+        .collect();
+    let issues = verify_library(&files, &tags);
+    for (index, issue) in issues.iter().enumerate() {
+        if index == 0 || issues[index - 1].severity != issue.severity {
+            let count = issues
+                .iter()
+                .filter(|other| other.severity == issue.severity)
+                .count();
+            println!("{} ({})", issue.severity.label(), count);
+        }
+        println!("  {}: {}", issue.file, issue.problem);
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(format!("{} errors in {} files", errors, files.len()).into());
+    }
+    println!("{} files checked, {} problems", files.len(), issues.len());
+    Ok(())
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "set") {
+        return run_set(&args[1..]);
+    }
+    if args.first().is_some_and(|command| command == "verify") {
+        return run_verify(&args[1..]);
+    }
+
+    let mut directories: Vec<String> = Vec::new();
+    let mut focus = None;
+    let mut probe = false;
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            "-p" | "--probe" => probe = true,
+            "-V" | "--version" => {
+                println!("metamusic {}", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            flag if flag.starts_with('-') => {
+                return Err(format!("unknown option '{}', see --help", flag).into());
+            }
+            _ => directories.push(arg),
+        }
+    }
+
+    // A file stands for its directory, and the first one given is selected at start
+    for path in directories.iter_mut() {
+        let file = Path::new(path.as_str());
+        if file.is_dir() {
+            continue;
+        }
+        if !file.is_file() {
+            return Err(format!("'{}' is not a file or directory", path).into());
+        }
+        let directory = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.display().to_string(),
+            _ => ".".to_string(),
+        };
+        if focus.is_none() {
+            focus = Some((
+                directory.clone(),
+                file.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+            ));
+        }
+        *path = directory;
+    }
+    let mut seen = std::collections::HashSet::new();
+    directories.retain(|directory| seen.insert(directory.clone()));
+
+    // A single directory is worked on from inside it, so file names stay short;
+    // with several, each file is listed with the directory it came from
+    let single = directories.len() == 1;
+    let roots = match directories.as_slice() {
+        [directory] => {
+            std::env::set_current_dir(directory)
+                .map_err(|e| format!("cannot open directory '{}': {}", directory, e))?;
+            Vec::new()
+        }
+        _ => directories,
+    };
+
+    // The app queries the terminal for its image support, which needs the terminal
+    // set up first
     let mut terminal = setup_terminal()?;
-    let app = App::new()?;
-    let result = run_app(&mut terminal, app);
+    let mut app = match App::new(&roots, probe) {
+        Ok(app) => app,
+        Err(e) => {
+            restore_terminal(&mut terminal)?;
+            return Err(e);
+        }
+    };
+    if let Some((directory, name)) = focus {
+        let file = if single {
+            name
+        } else {
+            Path::new(&directory).join(name).display().to_string()
+        };
+        app.select_file(&file);
+    }
+
+    let result = run_app(&mut terminal, &mut app);
     restore_terminal(&mut terminal)?;
+    if let Err(e) = app.save_state() {
+        eprintln!("✗ Cannot save the state: {}", e);
+    }
     result
 }
 
+/// The keys shared by every scrollable view.
+fn scroll_for(code: KeyCode) -> Option<Scroll> {
+    match code {
+        KeyCode::Up => Some(Scroll::Up),
+        KeyCode::Down => Some(Scroll::Down),
+        KeyCode::PageUp => Some(Scroll::PageUp),
+        KeyCode::PageDown => Some(Scroll::PageDown),
+        KeyCode::Home => Some(Scroll::Home),
+        KeyCode::End => Some(Scroll::End),
+        _ => None,
+    }
+}
+
 fn run_app(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-    mut app: App,
+    app: &mut App,
 ) -> Result<(), Box<dyn Error>> {
     loop {
-        terminal.draw(|f| ui(f, &mut app))?;
+        terminal.draw(|f| ui(f, app))?;
+
+        // While background work runs, wake up regularly to pick up its progress
+        if app.is_busy() && !event::poll(Duration::from_millis(100))? {
+            app.poll_background();
+            continue;
+        }
 
         if let Event::Key(key) = event::read()? {
+            // Ctrl+C quits from any mode, dropping an unfinished edit, but warns about
+            // buffered edits first just like q
+            if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+                if app.confirm_quit() {
+                    return Ok(());
+                }
+                continue;
+            }
+
             match app.mode() {
                 Mode::FileSelection => match key.code {
-                    KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('q') if app.confirm_quit() => return Ok(()),
                     KeyCode::Down => app.next_item(),
                     KeyCode::Up => app.previous_item(),
-                    KeyCode::Enter => app.start_field_selection(),
+                    KeyCode::Enter => app.open_form(),
+                    KeyCode::Char('F') => app.start_field_selection(),
+                    KeyCode::Char(' ') => app.toggle_file_selected(),
+                    KeyCode::Char('a') => app.select_all_files(),
+                    KeyCode::Esc => app.clear_file_selection(),
+                    KeyCode::Char('A') => app.start_quick_edit("Artist"),
+                    KeyCode::Char('T') => app.start_quick_edit("Song Name"),
+                    KeyCode::Char('c') => app.embed_sidecar_art(),
+                    KeyCode::Char('C') => app.start_prompt(PromptKind::AlbumArtDir),
+                    KeyCode::Char('x') => app.preview_remove_all_art(),
+                    KeyCode::Char('s') => app.preview_swap_artist_title(),
+                    KeyCode::Char('g') => app.start_replaygain(),
+                    KeyCode::Char('i') => app.start_prompt(PromptKind::ArtPath),
+                    KeyCode::Char('f') => app.start_prompt(PromptKind::ReplaceField),
+                    KeyCode::Char('r') => app.start_transform(),
+                    KeyCode::Char('n') => app.start_prompt(PromptKind::Rename),
+                    KeyCode::Char('N') => app.start_prompt(PromptKind::RenameTemplate),
+                    KeyCode::Char('e') => app.fix_extension(),
+                    KeyCode::Char('M') => app.start_prompt(PromptKind::Normalize),
+                    KeyCode::Char('S') => app.open_stats(),
+                    KeyCode::Char('V') => app.open_verify(),
+                    KeyCode::Char('B') => app.preview_unify_albums(),
+                    KeyCode::Char('D') => app.preview_compilations(),
+                    KeyCode::Char('G') => app.open_gallery(),
+                    KeyCode::Char('X') => app.preview_strip_tags(),
+                    KeyCode::Char('W') => app.open_review(),
+                    KeyCode::Char('Y') => app.yank_art(),
+                    KeyCode::Char('p') => app.paste_art(),
+                    KeyCode::Char('I') => app.paste_clipboard_art(),
+                    KeyCode::Char('u') => app.next_picture(),
+                    KeyCode::Char('z') => app.undo(),
+                    KeyCode::Char('U') => app.cycle_picture_type(),
+                    KeyCode::Char('H') => app.open_history(),
+                    KeyCode::Char('E') => app.open_table(),
+                    KeyCode::Char('m') => app.toggle_monochrome(),
+                    KeyCode::Char('K') => app.start_prompt(PromptKind::CopyByTrack),
+                    KeyCode::Char('J') => app.start_prompt(PromptKind::ExportJson),
+                    KeyCode::Char('O') => app.start_prompt(PromptKind::ImportJson),
+                    KeyCode::Char('v') => app.toggle_art(),
+                    KeyCode::Char('Q') => app.cycle_art_filter(),
+                    KeyCode::Char('P') => app.toggle_path_display(),
+                    KeyCode::Char('o') => app.toggle_album_sort(),
+                    KeyCode::Char('t') => app.preview_track_totals(false),
+                    KeyCode::Char('l') => app.open_log(),
+                    KeyCode::Char('+') => app.nudge_font_height(1),
+                    KeyCode::Char('-') => app.nudge_font_height(-1),
+                    KeyCode::Char('>') => app.nudge_files_width(5),
+                    KeyCode::Char('<') => app.nudge_files_width(-5),
+                    KeyCode::Char('y') => {
+                        if let Some(text) = tags_preview_text(app) {
+                            app.copy_to_clipboard(text);
+                        }
+                    }
                     _ => {}
                 },
                 Mode::FieldSelection => match key.code {
-                    KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('q') if app.confirm_quit() => return Ok(()),
                     KeyCode::Char('b') => app.back_to_files(),
+                    KeyCode::Char('a') => app.toggle_advanced_fields(),
+                    KeyCode::Char('#') => app.toggle_quick_track(),
+                    KeyCode::Char('n') => app.next_empty_field(),
+                    KeyCode::Char('?') => app.show_frame_info(),
+                    KeyCode::Char(c) if c.is_ascii_digit() => app.quick_set_track(c),
                     KeyCode::Down => app.next_item(),
                     KeyCode::Up => app.previous_item(),
                     KeyCode::Enter => app.start_editing(),
@@ -49,12 +342,117 @@ fn run_app(
                         }
                     }
                     KeyCode::Esc => app.cancel_editing(),
+                    KeyCode::Tab => app.reuse_last_value(),
+                    KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.edit_adjacent_file(1)
+                    }
+                    KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.edit_adjacent_file(-1)
+                    }
+                    KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        app.reset_field()
+                    }
                     KeyCode::Char(c) => app.push_to_buffer(c),
                     KeyCode::Backspace => {
                         app.pop_from_buffer();
                     }
                     _ => {}
                 },
+                Mode::Prompt => match key.code {
+                    KeyCode::Enter => app.finish_prompt(),
+                    KeyCode::Esc => app.cancel_prompt(),
+                    KeyCode::Tab => app.complete_prompt(),
+                    KeyCode::Char(c) => app.push_to_buffer(c),
+                    KeyCode::Backspace => app.pop_from_buffer(),
+                    _ => {}
+                },
+                Mode::Confirm => match key.code {
+                    KeyCode::Char('y') if !app.needs_strong_confirmation() => app.confirm_pending(),
+                    KeyCode::Char('Y') if app.needs_strong_confirmation() => app.confirm_pending(),
+                    KeyCode::Char('n') | KeyCode::Esc => app.cancel_pending(),
+                    KeyCode::Char(c) => app.toggle_pending_option(c),
+                    code => {
+                        if let Some(scroll) = scroll_for(code) {
+                            app.scroll_confirm(scroll);
+                        }
+                    }
+                },
+                Mode::FrameInfo => match key.code {
+                    KeyCode::Esc | KeyCode::Char('?') | KeyCode::Char('q') => {
+                        app.close_frame_info()
+                    }
+                    _ => {}
+                },
+                Mode::Review => match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') => app.close_review(),
+                    KeyCode::Char(' ') => app.toggle_review_item(),
+                    KeyCode::Char('d') => app.drop_review_item(),
+                    KeyCode::Enter | KeyCode::Char('w') => app.save_all(),
+                    code => {
+                        if let Some(scroll) = scroll_for(code) {
+                            app.scroll_review(scroll);
+                        }
+                    }
+                },
+                Mode::Stats => match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('S') => app.close_stats(),
+                    _ => {}
+                },
+                Mode::Gallery => match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('G') => app.close_gallery(),
+                    KeyCode::Enter => app.choose_gallery_file(),
+                    KeyCode::Left => app.move_gallery(-1, 0),
+                    KeyCode::Right => app.move_gallery(1, 0),
+                    KeyCode::Up => app.move_gallery(0, -1),
+                    KeyCode::Down => app.move_gallery(0, 1),
+                    _ => {}
+                },
+                Mode::Verify => match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('V') => app.close_verify(),
+                    code => {
+                        if let Some(scroll) = scroll_for(code) {
+                            app.scroll_verify(scroll);
+                        }
+                    }
+                },
+                Mode::Form => match key.code {
+                    KeyCode::Esc => app.close_form(),
+                    KeyCode::Enter => app.save_form(),
+                    KeyCode::Tab | KeyCode::Down => app.move_form_focus(1),
+                    KeyCode::BackTab | KeyCode::Up => app.move_form_focus(-1),
+                    KeyCode::Backspace => app.pop_form_char(),
+                    KeyCode::Char(c) => app.push_form_char(c),
+                    _ => {}
+                },
+                Mode::TableEdit => match key.code {
+                    KeyCode::Esc => app.close_table(),
+                    KeyCode::Enter => app.commit_table_row(),
+                    KeyCode::Tab => app.move_table_column(1),
+                    KeyCode::BackTab => app.move_table_column(-1),
+                    KeyCode::Backspace => app.pop_table_char(),
+                    KeyCode::Char(c) => app.push_table_char(c),
+                    code => {
+                        if let Some(scroll) = scroll_for(code) {
+                            app.scroll_table(scroll);
+                        }
+                    }
+                },
+                Mode::History => match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('H') => app.close_history(),
+                    code => {
+                        if let Some(scroll) = scroll_for(code) {
+                            app.scroll_history(scroll);
+                        }
+                    }
+                },
+                Mode::Log => match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('l') => app.close_log(),
+                    code => {
+                        if let Some(scroll) = scroll_for(code) {
+                            app.scroll_log(scroll);
+                        }
+                    }
+                },
             }
         }
     }
@@ -0,0 +1,137 @@
+use crate::decode::decode;
+use crate::functions::{read_tag_for_write, write_tag};
+
+use ebur128::{EbuR128, Mode};
+use id3::frame::ExtendedText;
+use id3::TagLike;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// ReplayGain 2.0 reference level, in LUFS.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Gain and peak to write to one file.
+pub struct Gain {
+    pub file: String,
+    pub track_gain: f64,
+    pub track_peak: f64,
+    /// Shared by every track of the same album, when the album is known.
+    pub album: Option<(f64, f64)>,
+}
+
+/// Reported by the analysis thread as it works through the files.
+pub enum Progress {
+    Analyzed { done: usize, total: usize },
+    Failed { file: String, error: String },
+    Finished(Vec<Gain>),
+}
+
+/// Analyses `albums` on a background thread. Each inner list is one album, whose
+/// tracks also get an album gain; tracks of unknown albums come as lists of one
+/// with `album_known` false.
+pub fn spawn(albums: Vec<(bool, Vec<String>)>) -> Receiver<Progress> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || analyze(albums, sender));
+    receiver
+}
+
+fn analyze(albums: Vec<(bool, Vec<String>)>, sender: Sender<Progress>) {
+    let total = albums.iter().map(|(_, files)| files.len()).sum();
+    let mut done = 0;
+    let mut gains = Vec::new();
+
+    for (album_known, files) in albums {
+        let mut meters = Vec::new();
+        for file in files {
+            match measure(&file) {
+                Ok(meter) => meters.push((file, meter)),
+                Err(error) => {
+                    let _ = sender.send(Progress::Failed { file, error });
+                }
+            }
+            done += 1;
+            let _ = sender.send(Progress::Analyzed { done, total });
+        }
+
+        let album = if album_known && !meters.is_empty() {
+            let loudness = EbuR128::loudness_global_multiple(meters.iter().map(|(_, meter)| meter));
+            let peak = meters
+                .iter()
+                .map(|(_, meter)| peak(meter))
+                .fold(0.0, f64::max);
+            loudness
+                .ok()
+                .map(|loudness| (REFERENCE_LOUDNESS - loudness, peak))
+        } else {
+            None
+        };
+
+        for (file, meter) in meters {
+            match meter.loudness_global() {
+                Ok(loudness) => gains.push(Gain {
+                    track_gain: REFERENCE_LOUDNESS - loudness,
+                    track_peak: peak(&meter),
+                    album,
+                    file,
+                }),
+                Err(e) => {
+                    let _ = sender.send(Progress::Failed {
+                        file,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    let _ = sender.send(Progress::Finished(gains));
+}
+
+/// Decodes the whole file through an EBU R128 meter.
+fn measure(path: &str) -> Result<EbuR128, String> {
+    let mut meter: Option<EbuR128> = None;
+    decode(path, |channels, rate, samples| {
+        let meter = match meter.as_mut() {
+            Some(meter) => meter,
+            None => {
+                let new = EbuR128::new(channels, rate, Mode::I | Mode::SAMPLE_PEAK)
+                    .map_err(|e| e.to_string())?;
+                meter.insert(new)
+            }
+        };
+        meter.add_frames_f32(samples).map_err(|e| e.to_string())
+    })?;
+
+    meter.ok_or_else(|| "no audio decoded".to_string())
+}
+
+fn peak(meter: &EbuR128) -> f64 {
+    (0..meter.channels())
+        .filter_map(|channel| meter.sample_peak(channel).ok())
+        .fold(0.0, f64::max)
+}
+
+/// Writes the standard REPLAYGAIN_* TXXX frames for `gain`.
+pub fn write_gain(gain: &Gain) -> Result<(), Box<dyn Error>> {
+    let mut tag = read_tag_for_write(&gain.file)?;
+
+    let mut set = |description: &str, value: String| {
+        tag.remove_extended_text(Some(description), None);
+        tag.add_frame(ExtendedText {
+            description: description.to_string(),
+            value,
+        });
+    };
+    set(
+        "REPLAYGAIN_TRACK_GAIN",
+        format!("{:.2} dB", gain.track_gain),
+    );
+    set("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", gain.track_peak));
+    if let Some((album_gain, album_peak)) = gain.album {
+        set("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", album_gain));
+        set("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album_peak));
+    }
+
+    write_tag(&tag, &gain.file)
+}
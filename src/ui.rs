@@ -1,5 +1,12 @@
+use crate::app::AlbumArt;
 use crate::app::App;
 use crate::app::Mode;
+use crate::app::PathDisplay;
+use crate::app::PendingAction;
+use crate::functions::{
+    field_input, find_sidecar_art, format_duration, format_modified, format_size, frame_info,
+    parse_filename, Severity, TABLE_COLUMNS, VARIOUS_ARTISTS,
+};
 
 use crossterm::{
     execute,
@@ -7,17 +14,18 @@ use crossterm::{
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Alignment, Constraint, Direction, Layout},
-    style::{Color, Style},
+    buffer::Buffer,
+    layout::{Alignment, Constraint, Direction, Layout, Position, Rect},
+    style::{Color, Modifier, Style},
     text::{Line, Span},
     widgets::{
-        Block, Borders, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation,
-        ScrollbarState, Wrap,
+        Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Scrollbar,
+        ScrollbarOrientation, ScrollbarState, Sparkline, Table, TableState, Wrap,
     },
     Frame, Terminal,
 };
-use ratatui_image::StatefulImage;
-use std::{error::Error, io};
+use ratatui_image::{picker::ProtocolType, Resize, StatefulImage};
+use std::{collections::HashSet, error::Error, io, path::Path};
 
 pub fn setup_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>, Box<dyn Error>> {
     enable_raw_mode()?;
@@ -34,7 +42,22 @@ pub fn restore_terminal(
     Ok(())
 }
 
+/// Smallest terminal the full layout is drawn in, as (columns, rows).
+const MIN_SIZE: (u16, u16) = (60, 20);
+
 pub fn ui(f: &mut Frame, app: &mut App) {
+    let area = f.area();
+    if area.width < MIN_SIZE.0 || area.height < MIN_SIZE.1 {
+        let message = Paragraph::new(format!(
+            "Terminal too small ({}×{}), need at least {}×{}",
+            area.width, area.height, MIN_SIZE.0, MIN_SIZE.1
+        ))
+        .style(Style::default().fg(Color::Yellow))
+        .wrap(Wrap { trim: true });
+        f.render_widget(message, area);
+        return;
+    }
+
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(1)
@@ -49,8 +72,18 @@ pub fn ui(f: &mut Frame, app: &mut App) {
         )
         .split(f.area());
 
-    // Title
-    let title = Paragraph::new("Metamusic - A Rust Tags Editor")
+    // Title, with as much of the scanned directories as fits
+    let heading = "Metamusic - A Rust Tags Editor";
+    let room = (chunks[0].width as usize)
+        .saturating_sub(2)
+        .saturating_sub(heading.chars().count() + 3);
+    let directory = truncate_left(app.location(), room);
+    let title_text = if directory.is_empty() {
+        heading.to_string()
+    } else {
+        format!("{} - {}", heading, directory)
+    };
+    let title = Paragraph::new(title_text)
         .alignment(Alignment::Center)
         .style(Style::default().fg(Color::Yellow))
         .block(Block::default().borders(Borders::ALL));
@@ -59,42 +92,98 @@ pub fn ui(f: &mut Frame, app: &mut App) {
     // Split the files area horizontally for files list and content
     let files_chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .constraints([
+            Constraint::Percentage(app.files_width()),
+            Constraint::Percentage(100 - app.files_width()),
+        ])
         .split(chunks[1]);
 
-    // Files list (left side)
-    let file_items: Vec<ListItem> = app
+    // Files list (left side), less the borders and the two marker columns
+    let name_width = (files_chunks[0].width as usize).saturating_sub(4);
+    let unreadable: Vec<bool> = app
         .files()
+        .to_vec()
         .iter()
-        .enumerate()
-        .map(|(i, file)| {
-            let style = if i == app.selected_file() && app.mode() == &Mode::FileSelection {
-                Style::default().fg(Color::Yellow)
-            } else if i == app.selected_file() {
-                Style::default().fg(Color::Green)
-            } else {
-                Style::default()
-            };
+        .map(|file| app.tags_for_file(file).is_none())
+        .collect();
+    let headings: Vec<Option<String>> = (0..app.files().len())
+        .map(|i| app.album_heading(i))
+        .collect();
+    let file_rows = app.files().iter().enumerate().map(|(i, file)| {
+        let style = if i == app.selected_file() && app.mode() == &Mode::FileSelection {
+            Style::default().fg(Color::Yellow)
+        } else if i == app.selected_file() {
+            Style::default().fg(Color::Green)
+        } else {
+            Style::default()
+        };
 
-            let display = if i == app.selected_file() {
-                format!("▶ {}", file)
-            } else {
-                format!("  {}", file)
-            };
+        let marker = if app.is_file_selected(file) {
+            "●"
+        } else {
+            " "
+        };
+        let cursor = if i == app.selected_file() { "▶" } else { " " };
+        let mut spans = vec![Span::styled(format!("{}{}", cursor, marker), style)];
 
-            ListItem::new(Line::from(Span::styled(display, style)))
-        })
-        .collect();
+        let label = match app.path_display() {
+            PathDisplay::Full => file.as_str(),
+            PathDisplay::Basename => Path::new(file)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(file),
+        };
+        // Files whose tag could not be parsed get flagged for triage
+        let name = if unreadable[i] {
+            spans.push(Span::styled("✗ ", Style::default().fg(Color::Red)));
+            truncate_middle(label, name_width.saturating_sub(2))
+        } else if app.has_buffered_edits(file) {
+            spans.push(Span::styled("✎ ", Style::default().fg(Color::Cyan)));
+            truncate_middle(label, name_width.saturating_sub(2))
+        } else if app.is_mislabeled(file) {
+            spans.push(Span::styled("? ", Style::default().fg(Color::Yellow)));
+            truncate_middle(label, name_width.saturating_sub(2))
+        } else {
+            truncate_middle(label, name_width)
+        };
+        spans.push(Span::styled(name, style));
+
+        ListItem::new(Line::from(spans))
+    });
+    // Album headings are rows of their own, so the list counts rows, not files
+    let mut file_items: Vec<ListItem> = Vec::new();
+    let mut selected_row = 0;
+    for (i, row) in file_rows.enumerate() {
+        if let Some(album) = &headings[i] {
+            let heading = format!("── {} ──", album);
+            file_items.push(ListItem::new(Span::styled(
+                truncate_middle(&heading, name_width + 2),
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            )));
+        }
+        if i == app.selected_file() {
+            selected_row = file_items.len();
+        }
+        file_items.push(row);
+    }
+    let row_count = file_items.len();
 
+    let files_noun = if app.sort_by_album() {
+        "MP3 Files by Album"
+    } else {
+        "MP3 Files"
+    };
+    let files_title = match app.selected_count() {
+        0 => format!("{} (↑↓ to select)", files_noun),
+        count => format!("{} ({} selected)", files_noun, count),
+    };
     let files_list = List::new(file_items)
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("MP3 Files (↑↓ to select)"),
-        )
+        .block(Block::default().borders(Borders::ALL).title(files_title))
         .highlight_style(Style::default().bg(Color::DarkGray));
 
-    let mut list_state = ListState::default().with_selected(Some(app.selected_file()));
+    let mut list_state = ListState::default().with_selected(Some(selected_row));
 
     f.render_stateful_widget(files_list, files_chunks[0], &mut list_state);
 
@@ -102,13 +191,21 @@ pub fn ui(f: &mut Frame, app: &mut App) {
         .begin_symbol(Some("↑"))
         .end_symbol(Some("↓"));
 
-    let mut scrollbar_state = ScrollbarState::new(app.files().len()).position(app.selected_file());
+    let mut scrollbar_state = ScrollbarState::new(row_count).position(selected_row);
     f.render_stateful_widget(scrollbar, files_chunks[0], &mut scrollbar_state);
 
-    // Right side: Split into tags and album art
+    // Right side: Split into tags and album art, or all tags when the art is hidden
+    let right_constraints = if app.show_art() {
+        vec![
+            Constraint::Percentage(app.preview_width()),
+            Constraint::Percentage(100 - app.preview_width()),
+        ]
+    } else {
+        vec![Constraint::Percentage(100)]
+    };
     let right_chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
+        .constraints(right_constraints)
         .split(files_chunks[1]);
 
     // Tags preview (left side of right panel)
@@ -116,15 +213,37 @@ pub fn ui(f: &mut Frame, app: &mut App) {
     f.render_widget(tags_preview, right_chunks[0]);
 
     // Album art (right side of right panel)
-    create_album_art_widget(f, app, right_chunks[1]);
+    if app.show_art() {
+        create_album_art_widget(f, app, right_chunks[1]);
+    }
 
     // Bottom panel - different content based on mode()
     match app.mode() {
         Mode::FileSelection => {
-            let instructions = Paragraph::new("Press ENTER to select this file and edit its tags")
-                .block(Block::default().borders(Borders::ALL).title("Instructions"))
-                .wrap(Wrap { trim: true });
-            f.render_widget(instructions, chunks[2]);
+            let width = chunks[2].width.saturating_sub(2) as usize;
+            let waveform = app
+                .files()
+                .get(app.selected_file())
+                .cloned()
+                .and_then(|file| app.waveform(&file, width));
+            match waveform {
+                Some(bars) => {
+                    let waveform = Sparkline::default()
+                        .block(Block::default().borders(Borders::ALL).title("Waveform"))
+                        .data(&bars)
+                        .max(100)
+                        .style(Style::default().fg(Color::Cyan));
+                    f.render_widget(waveform, chunks[2]);
+                }
+                None => {
+                    let instructions = Paragraph::new(
+                        "Press ENTER to edit the tags of this file, or F for one field",
+                    )
+                    .block(Block::default().borders(Borders::ALL).title("Instructions"))
+                    .wrap(Wrap { trim: true });
+                    f.render_widget(instructions, chunks[2]);
+                }
+            }
         }
         Mode::FieldSelection => {
             let field_items: Vec<ListItem> = app
@@ -142,13 +261,22 @@ pub fn ui(f: &mut Frame, app: &mut App) {
                 .collect();
 
             let fields_list = List::new(field_items)
-                .block(
-                    Block::default()
-                        .borders(Borders::ALL)
-                        .title("Fields to Edit (↑↓ to select, ENTER to edit)"),
-                )
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    "{} Fields to Edit (↑↓ to select, ENTER to edit){}",
+                    if app.advanced_fields() {
+                        "Advanced"
+                    } else {
+                        "Basic"
+                    },
+                    if app.quick_track() {
+                        " [quick track: 1-9]"
+                    } else {
+                        ""
+                    }
+                )))
                 .highlight_style(Style::default().bg(Color::DarkGray));
-            f.render_widget(fields_list, chunks[2]);
+            let mut field_state = ListState::default().with_selected(Some(app.selected_field()));
+            f.render_stateful_widget(fields_list, chunks[2], &mut field_state);
         }
         Mode::Editing => {
             let input_display = if app.input_buffer().is_empty() {
@@ -157,18 +285,134 @@ pub fn ui(f: &mut Frame, app: &mut App) {
                 app.input_buffer()
             };
 
-            let editing_panel = Paragraph::new(format!(
-                "Editing {}:\n\n{}\n\nType new value and press ENTER to save",
-                app.current_field()
-                    .as_ref()
-                    .unwrap_or(&&"Unknown".to_string()),
-                input_display
-            ))
-            .block(Block::default().borders(Borders::ALL).title("Editing Mode"))
-            .style(Style::default().fg(Color::Cyan))
-            .wrap(Wrap { trim: true });
+            let hint = match app.last_value() {
+                Some(last) => format!("Tab: reuse last value '{}'", last),
+                None => "Type new value and press ENTER to save".to_string(),
+            };
+            let field = app.current_field().cloned().unwrap_or_default();
+            let input = field_input(&field);
+
+            let mut lines = vec![
+                Line::from(format!(
+                    "Editing {}:",
+                    if field.is_empty() { "Unknown" } else { &field }
+                )),
+                Line::from(""),
+                Line::from(input_display.to_string()),
+            ];
+            // Warn before saving rather than after, while the value is still being typed
+            if let Some(input) = input
+                && !app.input_buffer().is_empty()
+                && !(input.is_valid)(app.input_buffer())
+            {
+                lines.push(Line::from(Span::styled(
+                    format!("✗ expected {}", input.hint),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            lines.push(Line::from(""));
+            lines.push(Line::from(hint));
+
+            let title = match input {
+                Some(input) => format!("Editing Mode ({})", input.hint),
+                None => "Editing Mode".to_string(),
+            };
+            let editing_panel = Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .style(Style::default().fg(Color::Cyan))
+                .wrap(Wrap { trim: true });
             f.render_widget(editing_panel, chunks[2]);
         }
+        Mode::Prompt => {
+            let mut lines = vec![
+                Line::from(format!(
+                    "{}:",
+                    app.prompt().map(|kind| kind.label()).unwrap_or("Input")
+                )),
+                Line::from(""),
+                Line::from(format!("{}_", app.input_buffer())),
+                Line::from(""),
+            ];
+
+            // Show the other candidates while cycling through completions
+            if app.completions().len() > 1 {
+                let spans: Vec<Span> = app
+                    .completions()
+                    .iter()
+                    .map(|completion| {
+                        let style = if completion == app.input_buffer() {
+                            Style::default().fg(Color::Yellow)
+                        } else {
+                            Style::default().fg(Color::Gray)
+                        };
+                        let name = completion
+                            .trim_end_matches('/')
+                            .rsplit('/')
+                            .next()
+                            .unwrap_or(completion);
+                        Span::styled(format!("{}  ", name), style)
+                    })
+                    .collect();
+                lines.push(Line::from(spans));
+            }
+
+            let prompt_panel = Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title("Input"))
+                .style(Style::default().fg(Color::Cyan))
+                .wrap(Wrap { trim: true });
+            f.render_widget(prompt_panel, chunks[2]);
+        }
+        Mode::Confirm if matches!(app.pending(), Some(PendingAction::RenameAll { .. })) => {
+            // A whole album of renames needs more room than the bottom panel
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_rename_preview(f, app, area);
+        }
+        Mode::Confirm => {
+            let confirm_panel = create_confirm_widget(app);
+            f.render_widget(confirm_panel, chunks[2]);
+        }
+        Mode::FrameInfo => {
+            let area = chunks[1].union(chunks[2]);
+            render_frame_info(f, app, area);
+        }
+        Mode::Review => {
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_review(f, app, area);
+        }
+        Mode::Stats => {
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_stats(f, app, area);
+        }
+        Mode::Verify => {
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_verify(f, app, area);
+        }
+        Mode::Gallery => {
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_gallery(f, app, area);
+        }
+        Mode::Form => render_form(f, app, chunks[2]),
+        Mode::TableEdit => {
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_table(f, app, area);
+        }
+        Mode::History => {
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_history(f, app, area);
+        }
+        Mode::Log => {
+            // The log covers the files and bottom panels so more of it fits
+            let area = chunks[1].union(chunks[2]);
+            f.render_widget(Clear, area);
+            render_log(f, app, area);
+        }
     }
 
     // Status/Message bar
@@ -181,64 +425,123 @@ pub fn ui(f: &mut Frame, app: &mut App) {
         Mode::FileSelection => " File Selection",
         Mode::FieldSelection => "✎ Field Selection",
         Mode::Editing => " Editing",
+        Mode::Prompt => " Input",
+        Mode::Confirm => " Confirm",
+        Mode::Log => " Log",
+        Mode::History => " History",
+        Mode::TableEdit => " Table Edit",
+        Mode::Form => " Edit Form",
+        Mode::FrameInfo => " Frame Info",
+        Mode::Stats => " Library Stats",
+        Mode::Verify => " Library Check",
+        Mode::Gallery => " Gallery",
+        Mode::Review => " Unsaved Edits",
     };
 
-    let mode_para = Paragraph::new(format!("{} | {}", mode_indicator, app.message()))
-        .style(Style::default().fg(Color::Cyan));
+    let unsaved = match app.pending_summary().len() {
+        0 => String::new(),
+        count => format!(", {} unsaved", count),
+    };
+    let mut status = vec![Span::raw(format!(
+        "{} | {} files, {}{} | ",
+        mode_indicator,
+        app.files().len(),
+        format_size(app.total_size()),
+        unsaved
+    ))];
+    let dots = completeness_dots(app);
+    if !dots.is_empty() {
+        status.extend(dots);
+        status.push(Span::raw(" | "));
+    }
+    status.push(Span::raw(app.message().to_string()));
+    let mode_para = Paragraph::new(Line::from(status)).style(Style::default().fg(Color::Cyan));
     f.render_widget(mode_para, status_chunks[0]);
 
     let help_text = match app.mode() {
-        Mode::FileSelection => "↑↓: Navigate | Enter: Select File | q: Quit",
-        Mode::FieldSelection => "↑↓: Navigate | Enter: Edit Field | b: Back to Files | q: Quit",
-        Mode::Editing => "Type: Edit | Enter: Save | Esc: Cancel | b: Back to Files",
+        Mode::FileSelection => "↑↓: Navigate | Enter: Edit Tags | F: Edit One Field | Space: Mark | a/Esc: Mark All/None | A/T: Edit Artist/Title | c: Embed Cover Image | q: Quit",
+        Mode::FieldSelection => {
+            "↑↓: Navigate | Enter: Edit Field | n: Next Empty | a: Advanced Fields | #: Quick Track | ?: Frame Info | b: Back to Files | q: Quit"
+        }
+        Mode::Editing if app.discard_requested() => "Esc: Discard Changes | Enter: Save | Type: Keep Editing",
+        Mode::Editing => "Type: Edit | Enter: Save | Ctrl+N/P: Save & Next/Prev File | Tab: Last Value | Ctrl+R: Reset | Esc: Cancel",
+        Mode::Prompt => "Type: Edit | Tab: Complete | Enter: Confirm | Esc: Cancel",
+        Mode::Confirm if app.needs_strong_confirmation() => "Y: Apply | n/Esc: Cancel",
+        Mode::Confirm if matches!(app.pending(), Some(PendingAction::Overwrite { .. })) => {
+            "y: Overwrite | n/Esc: Reload"
+        }
+        Mode::Confirm => "y: Apply | n/Esc: Cancel | ↑↓/PgUp/PgDn: Scroll",
+        Mode::Log => "↑↓/PgUp/PgDn/Home/End: Scroll | Esc/l: Close",
+        Mode::History => "↑↓/PgUp/PgDn/Home/End: Scroll | Esc/H: Close",
+        Mode::Form => "Type: Edit | Tab/Shift+Tab/↑↓: Next/Prev Field | Enter: Save All | Esc: Close",
+        Mode::TableEdit => "Type: Edit Cell | Tab/Shift+Tab: Next/Prev Column | ↑↓: Row | Enter: Save Row | Esc: Close",
+        Mode::FrameInfo => "Esc: Close",
+        Mode::Stats => "Esc/S: Close",
+        Mode::Verify => "↑↓/PgUp/PgDn/Home/End: Scroll | Esc/V: Close",
+        Mode::Gallery => "←→↑↓: Move | Enter: Go to File | Esc/G: Close",
+        Mode::Review => "↑↓: Move | Space: Include/Skip | d: Drop | w/Enter: Save Included | Esc: Close",
     };
 
     let help_para = Paragraph::new(help_text).style(Style::default().fg(Color::Gray));
     f.render_widget(help_para, status_chunks[1]);
-}
 
-fn create_tags_preview_widget(app: &App) -> Paragraph<'static> {
-    if let Some(current_file) = app.files().get(app.selected_file()) {
-        if let Some(tag_info) = app.tags_for_file(current_file) {
-            let mut lines = Vec::new();
-
-            // Album art status
-            let has_art = app.has_album_art(current_file);
-            let art_status_text = if has_art {
-                "✓ Album Art".to_string()
-            } else {
-                "✗ No Album Art".to_string()
+    if app.monochrome() {
+        // The cover is a picture, not UI, unless one of the big panels is drawn over it
+        let art_visible = app.show_art()
+            && match app.mode() {
+                Mode::FileSelection | Mode::FieldSelection | Mode::Editing | Mode::Prompt => true,
+                Mode::Confirm => !matches!(app.pending(), Some(PendingAction::RenameAll { .. })),
+                _ => false,
             };
+        strip_colors(f.buffer_mut(), art_visible.then(|| right_chunks[1]));
+    }
+}
 
-            lines.push(Line::from(Span::styled(
-                art_status_text,
-                if has_art {
-                    Style::default().fg(Color::Green)
-                } else {
-                    Style::default().fg(Color::Red)
-                },
-            )));
-            lines.push(Line::from(""));
+/// One dot per preview row of the selected file, and one for its cover: green when
+/// the value is there, red and hollow when it is missing.
+fn completeness_dots(app: &mut App) -> Vec<Span<'static>> {
+    let Some(current_file) = app.files().get(app.selected_file()).cloned() else {
+        return Vec::new();
+    };
+    let Some(tag_info) = app.tags_for_file(&current_file) else {
+        return Vec::new();
+    };
 
-            // Tag information
-            let tag_content = vec![
-                ("Title".to_string(), tag_info.title.clone()),
-                ("Artist".to_string(), tag_info.artist.clone()),
-                ("Album".to_string(), tag_info.album.clone()),
-                ("Year".to_string(), tag_info.year.clone()),
-                ("Track".to_string(), tag_info.track.clone()),
-            ];
+    let present = tag_info
+        .preview
+        .iter()
+        .map(|(_, _, value)| !value.trim().is_empty())
+        .chain([tag_info.has_art]);
+    present
+        .map(|present| match present {
+            true => Span::styled("●", Style::default().fg(Color::Green)),
+            false => Span::styled("○", Style::default().fg(Color::Red)),
+        })
+        .collect()
+}
 
-            for (field, value) in tag_content {
-                lines.push(Line::from(vec![
-                    Span::styled(
-                        format!("{:<8}: ", field),
-                        Style::default().fg(Color::Yellow),
-                    ),
-                    Span::styled(value, Style::default().fg(Color::White)),
-                ]));
+/// Drops every colour from what was drawn, except within `keep`. Highlighted cells
+/// turn to reverse video instead, so the selection stays visible.
+fn strip_colors(buffer: &mut Buffer, keep: Option<Rect>) {
+    let area = buffer.area;
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            if keep.is_some_and(|keep| keep.contains(Position { x, y })) {
+                continue;
             }
+            let cell = &mut buffer[(x, y)];
+            if cell.bg != Color::Reset {
+                cell.modifier.insert(Modifier::REVERSED);
+            }
+            cell.fg = Color::Reset;
+            cell.bg = Color::Reset;
+        }
+    }
+}
 
+fn create_tags_preview_widget(app: &mut App) -> Paragraph<'static> {
+    if let Some(current_file) = app.files().get(app.selected_file()).cloned() {
+        if let Some(lines) = tags_preview_lines(app, &current_file, "—") {
             Paragraph::new(lines)
                 .block(
                     Block::default()
@@ -247,9 +550,14 @@ fn create_tags_preview_widget(app: &App) -> Paragraph<'static> {
                 )
                 .wrap(Wrap { trim: true })
         } else {
-            Paragraph::new("Cannot read tags from this file")
+            let error = match app.read_error(&current_file) {
+                Some(error) => format!("Read error: {}", error),
+                None => "Cannot read tags from this file".to_string(),
+            };
+            Paragraph::new(error)
                 .block(Block::default().borders(Borders::ALL).title("Tags Preview"))
                 .style(Style::default().fg(Color::Red))
+                .wrap(Wrap { trim: true })
         }
     } else {
         Paragraph::new("Select a file to view its tags")
@@ -258,8 +566,1182 @@ fn create_tags_preview_widget(app: &App) -> Paragraph<'static> {
     }
 }
 
+/// The tags preview of the selected file as plain text, for copying elsewhere.
+pub fn tags_preview_text(app: &mut App) -> Option<String> {
+    let current_file = app.files().get(app.selected_file())?.clone();
+    let lines = tags_preview_lines(app, &current_file, "")?;
+
+    let mut text = format!("Tags: {}\n", current_file);
+    for line in lines {
+        for span in line.spans {
+            text.push_str(&span.content);
+        }
+        text.push('\n');
+    }
+    Some(text)
+}
+
+/// Preview lines for a file, with `placeholder` standing in for missing values.
+fn tags_preview_lines(
+    app: &mut App,
+    current_file: &str,
+    placeholder: &str,
+) -> Option<Vec<Line<'static>>> {
+    let tag_info = app.tags_for_file(current_file)?;
+    let mut lines = Vec::new();
+
+    // Album art status
+    let (art_status_text, art_status_style) = if tag_info.has_art {
+        ("✓ Album Art", Style::default().fg(Color::Green))
+    } else if find_sidecar_art(current_file).is_some() {
+        (
+            "◇ External Art (c to embed)",
+            Style::default().fg(Color::Yellow),
+        )
+    } else {
+        ("✗ No Album Art", Style::default().fg(Color::Red))
+    };
+
+    lines.push(Line::from(Span::styled(
+        art_status_text.to_string(),
+        art_status_style,
+    )));
+    // The art panel decodes the cover anyway, so this comes from its cache
+    if let Some(art) = app.load_album_art(current_file) {
+        lines.push(Line::from(Span::styled(
+            format!("Cover: {}×{} {}", art.width, art.height, art.format),
+            Style::default().fg(Color::Gray),
+        )));
+    }
+    lines.push(Line::from(""));
+
+    // Tag information, in the configured order, with unsaved edits in place of what
+    // is on disk
+    let mut unsaved = HashSet::new();
+    let mut tag_content = tag_info.preview.clone();
+    for edit in app.pending_summary() {
+        if edit.file != current_file {
+            continue;
+        }
+        let Some(info) = frame_info(&edit.field) else {
+            continue;
+        };
+        if let Some(row) = tag_content
+            .iter_mut()
+            .find(|(_, frame, _)| frame == info.id)
+        {
+            row.2 = match edit.new.trim().parse::<usize>() {
+                // Ratings are typed as a number but shown as stars
+                Ok(stars) if info.id == "POPM" && stars <= 5 => {
+                    format!("{}{}", "★".repeat(stars), "☆".repeat(5 - stars))
+                }
+                _ => edit.new.clone(),
+            };
+            unsaved.insert(row.1.clone());
+        }
+    }
+
+    // The file's place in its album, with the tracks the folder lacks
+    let position = app.album_position(current_file);
+
+    // File metadata
+    if let Some(info) = app.file_info(current_file) {
+        tag_content.push(("Size".to_string(), String::new(), format_size(info.size)));
+        if let Some(modified) = info.modified {
+            tag_content.push((
+                "Modified".to_string(),
+                String::new(),
+                format_modified(modified),
+            ));
+        }
+    }
+    let label_width = tag_content
+        .iter()
+        .map(|(label, _, _)| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+
+    // What the file name says, for spotting tags that disagree with it
+    let from_filename = parse_filename(current_file);
+    let implied = |frame: &str, value: &str| -> Option<String> {
+        let implied = match frame {
+            "TIT2" => from_filename.title.clone()?,
+            "TPE1" => from_filename.artist.clone()?,
+            "TRCK" => {
+                let track = from_filename.track?;
+                let number = value.split('/').next().unwrap_or(value);
+                if number.parse::<u32>().ok() == Some(track) {
+                    return None;
+                }
+                return Some(track.to_string());
+            }
+            _ => return None,
+        };
+        (!implied.eq_ignore_ascii_case(value.trim())).then_some(implied)
+    };
+
+    // The filename reading the other way round is a strong hint of swapped tags
+    let swapped = from_filename
+        .artist
+        .as_deref()
+        .zip(from_filename.title.as_deref())
+        .is_some_and(|(artist, title)| {
+            !artist.eq_ignore_ascii_case(title)
+                && artist.eq_ignore_ascii_case(tag_info.title.trim())
+                && title.eq_ignore_ascii_case(tag_info.artist.trim())
+        });
+
+    for (label, frame, value) in tag_content {
+        let mut spans = vec![Span::styled(
+            format!("{:<width$}: ", label, width = label_width),
+            Style::default().fg(Color::Yellow),
+        )];
+        if unsaved.contains(&frame) {
+            spans.push(Span::styled(
+                format!("{} ✎", value),
+                Style::default().fg(Color::Cyan),
+            ));
+            lines.push(Line::from(spans));
+            continue;
+        }
+        // Written this session: mark it, so it's clear what has been touched
+        if app
+            .changed_since_load(current_file, &frame, &value)
+            .is_some()
+        {
+            let shown = if value.is_empty() {
+                placeholder.to_string()
+            } else {
+                value
+            };
+            spans.push(Span::styled(
+                format!("{} ●", shown),
+                Style::default().fg(Color::Green),
+            ));
+            lines.push(Line::from(spans));
+            continue;
+        }
+        match implied(&frame, &value) {
+            Some(implied) => {
+                let shown = if value.is_empty() {
+                    placeholder.to_string()
+                } else {
+                    value
+                };
+                spans.push(Span::styled(shown, Style::default().fg(Color::Magenta)));
+                spans.push(Span::styled(
+                    format!(" ≠ filename: {}", implied),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            None if value.is_empty() => spans.push(Span::styled(
+                placeholder.to_string(),
+                Style::default().fg(Color::DarkGray),
+            )),
+            None => spans.push(Span::styled(value, Style::default().fg(Color::White))),
+        }
+        lines.push(Line::from(spans));
+    }
+
+    if let Some(position) = position {
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            format!(
+                "Track {} of {} on {}",
+                position.track, position.count, position.album
+            ),
+            Style::default().fg(Color::Gray),
+        )));
+        let missing_count = position.missing_count();
+        if missing_count > 0 {
+            // A bogus track total would otherwise list hundreds
+            let mut missing: Vec<String> = position
+                .missing()
+                .take(10)
+                .map(|track| track.to_string())
+                .collect();
+            if missing_count as usize > missing.len() {
+                missing.push(format!("… {} in all", missing_count));
+            }
+            lines.push(Line::from(Span::styled(
+                format!("Missing from the folder: {}", missing.join(", ")),
+                Style::default().fg(Color::Yellow),
+            )));
+        }
+    }
+
+    if app.is_mislabeled(current_file) {
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            "? MP3 audio under the wrong extension (e to rename to .mp3)",
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+
+    if swapped {
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            "⇄ Artist and Title look swapped (s to swap)",
+            Style::default().fg(Color::Magenta),
+        )));
+    }
+
+    Some(lines)
+}
+
+/// Shortens `text` to `max` characters by replacing its start with an ellipsis.
+fn truncate_left(text: &str, max: usize) -> String {
+    let len = text.chars().count();
+    if len <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let tail: String = text.chars().skip(len - (max - 1)).collect();
+    format!("…{}", tail)
+}
+
+/// Shortens `name` to `max` characters with an ellipsis in the middle, keeping the
+/// start and the extension readable.
+fn truncate_middle(name: &str, max: usize) -> String {
+    let len = name.chars().count();
+    if len <= max {
+        return name.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+
+    let keep = max - 1;
+    let extension = name
+        .rfind('.')
+        .map(|dot| name[dot..].chars().count())
+        .unwrap_or(0);
+    let tail = extension.max(keep / 3).min(keep);
+    let head: String = name.chars().take(keep - tail).collect();
+    let end: String = name.chars().skip(len - tail).collect();
+    format!("{}…{}", head, end)
+}
+
+fn render_frame_info(f: &mut Frame, app: &App, area: ratatui::layout::Rect) {
+    let field = app
+        .fields()
+        .get(app.selected_field())
+        .cloned()
+        .unwrap_or_default();
+
+    let label =
+        |text: &str| Span::styled(format!("{:<8}", text), Style::default().fg(Color::Yellow));
+    let lines = match frame_info(&field) {
+        Some(info) => vec![
+            Line::from(vec![
+                label("Frame"),
+                Span::styled(info.id, Style::default().fg(Color::Cyan)),
+                Span::raw(format!(" - {}", info.name)),
+            ]),
+            Line::from(""),
+            Line::from(vec![label("Meaning"), Span::raw(info.description)]),
+            Line::from(vec![label("Format"), Span::raw(info.format)]),
+        ],
+        None => vec![Line::from("No frame information for this field")],
+    };
+
+    // Centered popup over the lower part of the screen
+    let popup = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(8)])
+        .split(area)[1];
+    f.render_widget(Clear, popup);
+    let info_panel = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("ID3 Frame: {}", field)),
+        )
+        .wrap(Wrap { trim: true });
+    f.render_widget(info_panel, popup);
+}
+
+fn render_log(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let items: Vec<ListItem> = if app.log_entries().is_empty() {
+        vec![ListItem::new(Span::styled(
+            "No changes written this session",
+            Style::default().fg(Color::Gray),
+        ))]
+    } else {
+        app.log_entries()
+            .iter()
+            .map(|entry| ListItem::new(entry.clone()))
+            .collect()
+    };
+
+    let title = format!("Session Log ({} changes)", app.log_entries().len());
+    let height = ScrollableList::new(title, items).render(f, area, app.log_scroll());
+    app.set_view_height(height);
+}
+
+fn render_history(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let Some((file, changes)) = app.history() else {
+        return;
+    };
+    let items: Vec<ListItem> = if changes.is_empty() {
+        vec![ListItem::new(Span::styled(
+            "No changes written to this file this session",
+            Style::default().fg(Color::Gray),
+        ))]
+    } else {
+        changes
+            .iter()
+            .map(|record| {
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        format!("{} ", record.time),
+                        Style::default().fg(Color::Gray),
+                    ),
+                    Span::raw(record.change.clone()),
+                ]))
+            })
+            .collect()
+    };
+
+    let title = format!("History of {} ({} changes)", file, changes.len());
+    let height = ScrollableList::new(title, items).render(f, area, app.history_scroll());
+    app.set_view_height(height);
+}
+
+fn render_verify(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let Some(issues) = app.issues() else {
+        return;
+    };
+    let items: Vec<ListItem> = if issues.is_empty() {
+        vec![ListItem::new(Span::styled(
+            "No problems found",
+            Style::default().fg(Color::Green),
+        ))]
+    } else {
+        issues
+            .iter()
+            .enumerate()
+            .map(|(index, issue)| {
+                let color = match issue.severity {
+                    Severity::Error => Color::Red,
+                    Severity::Warning => Color::Yellow,
+                    Severity::Notice => Color::Gray,
+                };
+                let mut lines = Vec::new();
+                // Each severity opens with a heading counting its issues
+                if index == 0 || issues[index - 1].severity != issue.severity {
+                    let count = issues
+                        .iter()
+                        .filter(|other| other.severity == issue.severity)
+                        .count();
+                    lines.push(Line::from(Span::styled(
+                        format!("{} ({})", issue.severity.label(), count),
+                        Style::default().fg(color).add_modifier(Modifier::BOLD),
+                    )));
+                }
+                lines.push(Line::from(vec![
+                    Span::styled(format!("  {}: ", issue.file), Style::default().fg(color)),
+                    Span::raw(issue.problem.clone()),
+                ]));
+                ListItem::new(lines)
+            })
+            .collect()
+    };
+
+    let title = format!("Library Check ({} problems)", issues.len());
+    let height = ScrollableList::new(title, items).render(f, area, app.issues_scroll());
+    app.set_view_height(height);
+}
+
+fn render_gallery(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("Gallery ({} files)", app.files().len()));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let columns = app.gallery_columns(inner.width);
+    let cell_width = inner.width / columns as u16;
+    // Covers are mostly square, so a cell is about as many pixels high as it is wide,
+    // plus its borders
+    let (font_width, font_height) = app
+        .image_picker
+        .as_ref()
+        .map_or((10, 24), |picker| picker.font_size());
+    let cover_height = u32::from(cell_width.saturating_sub(2)) * u32::from(font_width)
+        / u32::from(font_height.max(1));
+    let cell_height = (cover_height as u16 + 2).clamp(4, inner.height.max(4));
+    let rows = (inner.height / cell_height).max(1) as usize;
+    app.set_gallery_size(columns, rows);
+
+    // Scroll just enough to keep the selection on screen; only what is shown is decoded
+    let selected = app.gallery_selected();
+    let top_row = (selected / columns).saturating_sub(rows - 1);
+    for row in 0..rows {
+        for column in 0..columns {
+            let index = (top_row + row) * columns + column;
+            let Some(file) = app.files().get(index).cloned() else {
+                return;
+            };
+            let cell = Rect {
+                x: inner.x + column as u16 * cell_width,
+                y: inner.y + row as u16 * cell_height,
+                width: cell_width,
+                height: cell_height.min(
+                    inner
+                        .bottom()
+                        .saturating_sub(inner.y + row as u16 * cell_height),
+                ),
+            };
+            render_gallery_cell(f, app, cell, &file, index == selected);
+        }
+    }
+}
+
+fn render_gallery_cell(f: &mut Frame, app: &mut App, cell: Rect, file: &str, selected: bool) {
+    let name = Path::new(file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(file);
+    let border = if selected {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default().fg(Color::DarkGray)
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(border)
+        .title(truncate_middle(name, cell.width.saturating_sub(2) as usize));
+    let inner = block.inner(cell);
+    f.render_widget(block, cell);
+
+    // Files known to have no cover are not read again on every frame
+    let may_have_art = app
+        .tags_for_file(file)
+        .is_some_and(|tag_info| tag_info.has_art)
+        || find_sidecar_art(file).is_some();
+    let art = may_have_art.then(|| app.load_album_art(file)).flatten();
+    let font_size = app
+        .image_picker
+        .as_ref()
+        .map_or((10, 24), |picker| picker.font_size());
+    match art {
+        Some(AlbumArt {
+            protocol: Some(protocol),
+            width,
+            height,
+            ..
+        }) if inner.width >= 3 && inner.height >= 3 => {
+            if let Ok(mut protocol) = protocol.lock() {
+                let area = center_area(inner, (width, height), font_size);
+                let image = StatefulImage::default().resize(Resize::Fit(Some(app.art_filter())));
+                f.render_stateful_widget(image, area, &mut *protocol);
+            }
+        }
+        _ => {
+            let note = Paragraph::new("♬")
+                .alignment(Alignment::Center)
+                .style(Style::default().fg(Color::DarkGray));
+            let middle = Rect {
+                y: inner.y + inner.height / 2,
+                height: inner.height.min(1),
+                ..inner
+            };
+            f.render_widget(note, middle);
+        }
+    }
+}
+
+fn render_form(f: &mut Frame, app: &App, area: ratatui::layout::Rect) {
+    let Some(form) = app.form() else {
+        return;
+    };
+    let label_width = form.fields.iter().map(|field| field.chars().count()).max();
+    let label_width = label_width.unwrap_or(0);
+    let items: Vec<ListItem> = form
+        .fields
+        .iter()
+        .zip(&form.values)
+        .enumerate()
+        .map(|(index, (field, value))| {
+            let focused = index == form.focus;
+            let style = if focused {
+                Style::default().fg(Color::Yellow)
+            } else if form.is_changed(index) {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            let marker = if form.is_changed(index) { "✎" } else { " " };
+            let text = if focused {
+                format!("{}_", value)
+            } else {
+                value.clone()
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{} {:<width$}  ", marker, field, width = label_width),
+                    Style::default().fg(Color::Gray),
+                ),
+                Span::styled(text, style),
+            ]))
+        })
+        .collect();
+
+    let changed = (0..form.fields.len())
+        .filter(|index| form.is_changed(*index))
+        .count();
+    let name = Path::new(&form.file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&form.file);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Edit {} ({} changed)", name, changed)),
+        )
+        .highlight_style(Style::default().bg(Color::DarkGray));
+    let mut state = ListState::default().with_selected(Some(form.focus));
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+fn render_table(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let Some(table) = app.table() else {
+        return;
+    };
+    let header = Row::new(
+        std::iter::once("File")
+            .chain(TABLE_COLUMNS.iter().copied())
+            .map(|label| Cell::from(label).style(Style::default().fg(Color::Yellow))),
+    );
+    let rows: Vec<Row> = table
+        .rows
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let name = Path::new(&row.file)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(&row.file);
+            let marker = if row.is_dirty() { "✎ " } else { "  " };
+            let mut cells = vec![Cell::from(format!("{}{}", marker, name))];
+            for (column, value) in row.values.iter().enumerate() {
+                let current = index == table.row && column == table.column;
+                let style = if current {
+                    Style::default().fg(Color::Black).bg(Color::Yellow)
+                } else if *value != row.saved[column] {
+                    Style::default().fg(Color::Cyan)
+                } else {
+                    Style::default()
+                };
+                let text = if current {
+                    format!("{}_", value)
+                } else {
+                    value.clone()
+                };
+                cells.push(Cell::from(text).style(style));
+            }
+            Row::new(cells)
+        })
+        .collect();
+
+    let dirty = table.rows.iter().filter(|row| row.is_dirty()).count();
+    let title = format!(
+        "Table Edit ({} files, {} rows unsaved)",
+        table.rows.len(),
+        dirty
+    );
+    let widths = [
+        Constraint::Percentage(28),
+        Constraint::Percentage(24),
+        Constraint::Percentage(20),
+        Constraint::Percentage(20),
+        Constraint::Percentage(8),
+    ];
+    let widget = Table::new(rows, widths)
+        .header(header)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .row_highlight_style(Style::default().bg(Color::DarkGray));
+    let mut state = TableState::default().with_selected(Some(table.row));
+    f.render_stateful_widget(widget, area, &mut state);
+
+    // Less the borders and the header
+    app.set_view_height(area.height.saturating_sub(3) as usize);
+}
+
+fn render_review(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let edits = app.pending_summary();
+    let included = edits.iter().filter(|edit| edit.enabled).count();
+    let items: Vec<ListItem> = edits
+        .iter()
+        .map(|edit| {
+            let (mark, style) = if edit.enabled {
+                ("[x]", Style::default())
+            } else {
+                ("[ ]", Style::default().fg(Color::DarkGray))
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{} {}  ", mark, edit.file), style),
+                Span::styled(
+                    format!("{}: ", edit.field),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(format!("'{}'", edit.old)),
+                Span::styled(" → ", Style::default().fg(Color::Gray)),
+                Span::styled(format!("'{}'", edit.new), Style::default().fg(Color::Green)),
+            ]))
+        })
+        .collect();
+
+    let title = format!("Unsaved Edits ({} of {} included)", included, edits.len());
+    let position = app.review_position();
+    let height = ScrollableList::new(title, items).render(f, area, position);
+    app.set_view_height(height);
+}
+
+fn render_stats(f: &mut Frame, app: &App, area: ratatui::layout::Rect) {
+    let Some(stats) = app.stats() else {
+        return;
+    };
+    let label =
+        |text: &str| Span::styled(format!("{:<16}", text), Style::default().fg(Color::Yellow));
+    let count = |missing: usize| {
+        let style = if missing == 0 {
+            Style::default().fg(Color::Green)
+        } else {
+            Style::default().fg(Color::Red)
+        };
+        Span::styled(missing.to_string(), style)
+    };
+
+    let mut lines = vec![
+        Line::from(vec![label("Files"), Span::raw(stats.files.to_string())]),
+        Line::from(vec![
+            label("Total duration"),
+            Span::raw(
+                stats
+                    .duration
+                    .map_or("measuring…".to_string(), format_duration),
+            ),
+            Span::styled(
+                if stats.unknown_duration > 0 {
+                    format!(" ({} files unknown)", stats.unknown_duration)
+                } else {
+                    String::new()
+                },
+                Style::default().fg(Color::DarkGray),
+            ),
+        ]),
+        Line::from(vec![label("Artists"), Span::raw(stats.artists.to_string())]),
+        Line::from(vec![label("Albums"), Span::raw(stats.albums.to_string())]),
+        Line::from(""),
+        Line::from(vec![label("Unreadable tags"), count(stats.unreadable)]),
+        Line::from(vec![label("Without art"), count(stats.without_art)]),
+    ];
+    for (field, missing) in &stats.missing {
+        lines.push(Line::from(vec![
+            label(&format!("Missing {}", field)),
+            count(*missing),
+        ]));
+    }
+
+    let stats_panel = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Library Stats"),
+        )
+        .wrap(Wrap { trim: false });
+    f.render_widget(stats_panel, area);
+}
+
+/// A bordered list that keeps `position` highlighted and in view, with a scrollbar
+/// alongside. Every full-panel view scrolls through one of these.
+struct ScrollableList<'a> {
+    title: String,
+    items: Vec<ListItem<'a>>,
+}
+
+impl<'a> ScrollableList<'a> {
+    fn new(title: String, items: Vec<ListItem<'a>>) -> Self {
+        ScrollableList { title, items }
+    }
+
+    /// Draws the list and returns how many lines fit, for paging.
+    fn render(self, f: &mut Frame, area: ratatui::layout::Rect, position: usize) -> usize {
+        let len = self.items.len();
+        let list = List::new(self.items)
+            .block(Block::default().borders(Borders::ALL).title(self.title))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        let mut list_state = ListState::default().with_selected(Some(position));
+        f.render_stateful_widget(list, area, &mut list_state);
+
+        let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+            .begin_symbol(Some("↑"))
+            .end_symbol(Some("↓"));
+        let mut scrollbar_state = ScrollbarState::new(len).position(position);
+        f.render_stateful_widget(scrollbar, area, &mut scrollbar_state);
+
+        area.height.saturating_sub(2) as usize
+    }
+}
+
+fn render_rename_preview(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let Some(PendingAction::RenameAll {
+        renames,
+        tag_changes,
+    }) = app.pending()
+    else {
+        return;
+    };
+
+    let blocked = renames
+        .iter()
+        .filter(|(_, _, problem)| problem.is_some())
+        .count();
+    let items: Vec<ListItem> = renames
+        .iter()
+        .map(|(old, target, problem)| {
+            let mut lines = vec![match problem {
+                Some(problem) => Line::from(vec![
+                    Span::styled(format!("✗ {}", old), Style::default().fg(Color::Red)),
+                    Span::styled(
+                        format!(": {}", problem),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                ]),
+                None if old == target => Line::from(format!("  {}", old)),
+                None => Line::from(vec![
+                    Span::raw(format!("  {}", old)),
+                    Span::styled(" → ", Style::default().fg(Color::Gray)),
+                    Span::styled(target.clone(), Style::default().fg(Color::Green)),
+                ]),
+            }];
+            if problem.is_none() {
+                for (field, previous, value) in tag_changes.get(old).into_iter().flatten() {
+                    lines.push(Line::from(vec![
+                        Span::styled(
+                            format!("      {}: ", field),
+                            Style::default().fg(Color::Yellow),
+                        ),
+                        Span::raw(format!("'{}'", previous)),
+                        Span::styled(" → ", Style::default().fg(Color::Gray)),
+                        Span::styled(format!("'{}'", value), Style::default().fg(Color::Green)),
+                    ]));
+                }
+            }
+            ListItem::new(lines)
+        })
+        .collect();
+
+    let title = format!(
+        "{} {} files{}",
+        if tag_changes.is_empty() {
+            "Rename"
+        } else {
+            "Normalize"
+        },
+        renames.len() - blocked,
+        if blocked > 0 {
+            format!(", {} skipped", blocked)
+        } else {
+            String::new()
+        }
+    );
+    let position = app.confirm_scroll();
+    let height = ScrollableList::new(title, items).render(f, area, position);
+    app.set_view_height(height);
+}
+
+fn create_confirm_widget(app: &App) -> Paragraph<'static> {
+    let mut lines = Vec::new();
+    let title = match app.pending() {
+        Some(PendingAction::Replace { find, affected }) => {
+            lines.push(Line::from(vec![
+                Span::styled("In ", Style::default().fg(Color::Gray)),
+                Span::styled(find.field.clone(), Style::default().fg(Color::Yellow)),
+                Span::styled(": '", Style::default().fg(Color::Gray)),
+                Span::raw(find.search.clone()),
+                Span::styled("' → '", Style::default().fg(Color::Gray)),
+                Span::raw(find.replacement.clone()),
+                Span::styled("'", Style::default().fg(Color::Gray)),
+            ]));
+            lines.push(Line::from(format!(
+                "[i] {} | [w] {}",
+                if find.case_insensitive {
+                    "case-insensitive"
+                } else {
+                    "case-sensitive"
+                },
+                if find.whole_field {
+                    "whole field"
+                } else {
+                    "substring"
+                }
+            )));
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                format!("{} files will be changed", affected.len()),
+                Style::default().fg(Color::Cyan),
+            )));
+            for (file, value) in affected {
+                lines.push(Line::from(format!("  {}: {}", file, value)));
+            }
+            "Find & Replace"
+        }
+        Some(PendingAction::SwapArtistTitle {
+            file,
+            artist,
+            title,
+        }) => {
+            let label = |text: &str| {
+                Span::styled(format!("{:<8}", text), Style::default().fg(Color::Yellow))
+            };
+            lines.push(Line::from(Span::styled(
+                file.clone(),
+                Style::default().fg(Color::Cyan),
+            )));
+            lines.push(Line::from(""));
+            lines.push(Line::from(vec![
+                label("Artist"),
+                Span::raw(format!("{} → {}", artist, title)),
+            ]));
+            lines.push(Line::from(vec![
+                label("Title"),
+                Span::raw(format!("{} → {}", title, artist)),
+            ]));
+            "Swap Artist and Title"
+        }
+        Some(PendingAction::RemoveArt { affected }) => {
+            let total: u64 = affected.iter().map(|(_, bytes)| bytes).sum();
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "Remove all embedded art from {} files ({} of pictures)",
+                    affected.len(),
+                    format_size(total)
+                ),
+                Style::default().fg(Color::Red),
+            )));
+            lines.push(Line::from(""));
+            for (file, bytes) in affected {
+                lines.push(Line::from(format!("  {}: {}", file, format_size(*bytes))));
+            }
+            "Remove Album Art"
+        }
+        Some(PendingAction::Transform {
+            name,
+            field,
+            changes,
+        }) => {
+            lines.push(Line::from(vec![
+                Span::styled(name.clone(), Style::default().fg(Color::Yellow)),
+                Span::styled(" on ", Style::default().fg(Color::Gray)),
+                Span::raw(field.clone()),
+            ]));
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                format!("{} files will be changed", changes.len()),
+                Style::default().fg(Color::Cyan),
+            )));
+            for (file, old, new) in changes {
+                lines.push(Line::from(format!("  {}", file)));
+                lines.push(Line::from(format!("      '{}' → '{}'", old, new)));
+            }
+            "Transform"
+        }
+        Some(PendingAction::UnifyAlbums { conflicts }) => {
+            let changes: usize = conflicts
+                .iter()
+                .map(|conflict| {
+                    let target = conflict.majority();
+                    conflict
+                        .values
+                        .iter()
+                        .filter(|(value, _)| value != target)
+                        .map(|(_, files)| files.len())
+                        .sum::<usize>()
+                })
+                .sum();
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "{} fields disagree, {} values will be changed to the most common one",
+                    conflicts.len(),
+                    changes
+                ),
+                Style::default().fg(Color::Cyan),
+            )));
+            let mut folder = None;
+            for conflict in conflicts {
+                if folder != Some(&conflict.folder) {
+                    lines.push(Line::from(""));
+                    lines.push(Line::from(format!("{}/", conflict.folder)));
+                    folder = Some(&conflict.folder);
+                }
+                let target = conflict.majority();
+                let mut spans = vec![Span::styled(
+                    format!("  {}: ", conflict.field),
+                    Style::default().fg(Color::Yellow),
+                )];
+                for (index, (value, files)) in conflict.values.iter().enumerate() {
+                    if index > 0 {
+                        spans.push(Span::raw(", "));
+                    }
+                    let text = if value.is_empty() {
+                        format!("(none) ×{}", files.len())
+                    } else {
+                        format!("'{}' ×{}", value, files.len())
+                    };
+                    let style = if value == target {
+                        Style::default().fg(Color::Green)
+                    } else {
+                        Style::default()
+                    };
+                    spans.push(Span::styled(text, style));
+                }
+                lines.push(Line::from(spans));
+            }
+            "Unify Album Fields"
+        }
+        Some(PendingAction::MarkCompilations { compilations }) => {
+            let files: usize = compilations
+                .iter()
+                .map(|compilation| compilation.files.len())
+                .sum();
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "{} files will get album artist '{}' and the compilation flag",
+                    files, VARIOUS_ARTISTS
+                ),
+                Style::default().fg(Color::Cyan),
+            )));
+            for compilation in compilations {
+                lines.push(Line::from(""));
+                lines.push(Line::from(vec![
+                    Span::raw(format!("{}/ ", compilation.folder)),
+                    Span::styled(
+                        compilation.album.clone(),
+                        Style::default().fg(Color::Yellow),
+                    ),
+                ]));
+                let artists: Vec<String> = compilation
+                    .artists
+                    .iter()
+                    .map(|(artist, count)| {
+                        let artist = if artist.is_empty() { "(none)" } else { artist };
+                        format!("'{}' ×{}", artist, count)
+                    })
+                    .collect();
+                lines.push(Line::from(format!(
+                    "  {} tracks, {} artists: {}",
+                    compilation.files.len(),
+                    compilation.artists.len(),
+                    artists.join(", ")
+                )));
+            }
+            "Mark Compilations"
+        }
+        Some(PendingAction::Overwrite { file, field, value }) => {
+            lines.push(Line::from(Span::styled(
+                format!("{} was changed by another program since it was read.", file),
+                Style::default().fg(Color::Yellow),
+            )));
+            lines.push(Line::from(""));
+            lines.push(Line::from(format!(
+                "Write {} '{}' over it anyway?",
+                field, value
+            )));
+            lines.push(Line::from(Span::styled(
+                "n reloads the file and drops the edit",
+                Style::default().fg(Color::Gray),
+            )));
+            "File Changed on Disk"
+        }
+        Some(PendingAction::ImportJson { source, plan }) => {
+            lines.push(Line::from(vec![
+                Span::styled("From ", Style::default().fg(Color::Gray)),
+                Span::styled(source.clone(), Style::default().fg(Color::Yellow)),
+            ]));
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "{} files will be changed, {} not found, {} values refused",
+                    plan.changes.len(),
+                    plan.missing.len(),
+                    plan.invalid.len()
+                ),
+                Style::default().fg(Color::Cyan),
+            )));
+            for (file, edits) in &plan.changes {
+                lines.push(Line::from(format!("  {}", file)));
+                for edit in edits {
+                    let (what, old, new) = edit.describe();
+                    lines.push(Line::from(format!("      {}: '{}' → '{}'", what, old, new)));
+                }
+            }
+            for file in &plan.missing {
+                lines.push(Line::from(Span::styled(
+                    format!("  {}: not found", file),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            for (file, problem) in &plan.invalid {
+                lines.push(Line::from(Span::styled(
+                    format!("  {}: {}", file, problem),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            "Import Tags"
+        }
+        Some(PendingAction::CopyByTrack {
+            target_dir,
+            matches,
+            unmatched,
+        }) => {
+            lines.push(Line::from(vec![
+                Span::styled("To ", Style::default().fg(Color::Gray)),
+                Span::styled(target_dir.clone(), Style::default().fg(Color::Yellow)),
+            ]));
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "{} files will be changed, {} without a match",
+                    matches.len(),
+                    unmatched.len()
+                ),
+                Style::default().fg(Color::Cyan),
+            )));
+            for track_match in matches {
+                lines.push(Line::from(format!(
+                    "  {} ← {}",
+                    track_match.target, track_match.source
+                )));
+                for (field, old, new) in &track_match.edits {
+                    lines.push(Line::from(format!(
+                        "      {}: '{}' → '{}'",
+                        field, old, new
+                    )));
+                }
+            }
+            for (target, reason) in unmatched {
+                lines.push(Line::from(Span::styled(
+                    format!("  {}: {}", target, reason),
+                    Style::default().fg(Color::DarkGray),
+                )));
+            }
+            "Copy Tags by Track"
+        }
+        Some(PendingAction::TrackTotals {
+            folder,
+            renumber,
+            changes,
+        }) => {
+            let applied = changes.iter().filter(|(_, _, new)| new.is_some()).count();
+            lines.push(Line::from(vec![
+                Span::styled("In ", Style::default().fg(Color::Gray)),
+                Span::styled(folder.clone(), Style::default().fg(Color::Yellow)),
+            ]));
+            lines.push(Line::from(format!(
+                "[r] {}",
+                if *renumber {
+                    "renumber in list order"
+                } else {
+                    "keep track numbers"
+                }
+            )));
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                format!("{} files will be changed", applied),
+                Style::default().fg(Color::Cyan),
+            )));
+            for (file, old, new) in changes {
+                lines.push(match new {
+                    Some(new) => Line::from(format!(
+                        "  {}: {} → {}",
+                        file,
+                        if old.is_empty() { "—" } else { old },
+                        new
+                    )),
+                    None => Line::from(Span::styled(
+                        format!("  {}: no track number (r to renumber)", file),
+                        Style::default().fg(Color::DarkGray),
+                    )),
+                });
+            }
+            "Track Totals"
+        }
+        Some(PendingAction::StripTags { file }) => {
+            lines.push(Line::from(Span::styled(
+                format!("Remove every tag from {}", file),
+                Style::default().fg(Color::Red),
+            )));
+            lines.push(Line::from(""));
+            lines.push(Line::from(
+                "Title, artist, album, art, ratings and all other frames are lost for good; \
+                 the audio is left as it is.",
+            ));
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                "Press Y (shift+y) to strip, n/Esc to cancel",
+                Style::default().fg(Color::Yellow),
+            )));
+            "Strip All Tags"
+        }
+        // Drawn full size by render_rename_preview
+        Some(PendingAction::RenameAll { .. }) | None => "Confirm",
+    };
+
+    Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .wrap(Wrap { trim: false })
+}
+
 fn create_album_art_widget(f: &mut Frame, app: &mut App, area: ratatui::layout::Rect) {
-    let block = Block::default().borders(Borders::ALL).title("♬ Album Art");
+    let Some(picker) = app.image_picker.as_ref() else {
+        let block = Block::default().borders(Borders::ALL).title("♬ Album Art");
+        let inner_area = block.inner(area);
+        f.render_widget(block, area);
+        show_album_art_placeholder(f, app, inner_area, Some("Image support unavailable"));
+        return;
+    };
+    let (protocol_type, font_size) = (picker.protocol_type(), picker.font_size());
+
+    let current_file = app.files().get(app.selected_file()).cloned();
+    let art = current_file
+        .as_deref()
+        .and_then(|current_file| app.load_album_art(current_file));
+
+    // Badge art that comes from a cover image next to the track rather than the tag
+    let title = match art.as_ref().and_then(|art| art.sidecar.as_ref()) {
+        Some(sidecar) => format!(
+            "♬ Album Art [external: {}]",
+            sidecar
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default()
+        ),
+        // With several pictures, say which is shown and what the others are
+        None => match current_file
+            .as_deref()
+            .and_then(|current_file| app.tags_for_file(current_file))
+        {
+            Some(tag_info) if tag_info.picture_types.len() > 1 => {
+                format!("♬ Album Art [{}]", tag_info.picture_types.join(" · "))
+            }
+            _ => "♬ Album Art".to_string(),
+        },
+    };
+    // Without a graphics protocol the art is drawn with unicode half blocks
+    let title = if protocol_type == ProtocolType::Halfblocks {
+        format!("{} (text mode)", title)
+    } else {
+        title
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
 
     // Draw the block first
     f.render_widget(&block, area);
@@ -276,38 +1758,74 @@ fn create_album_art_widget(f: &mut Frame, app: &mut App, area: ratatui::layout::
         return;
     }
 
-    let current_file = app.files().get(app.selected_file()).cloned();
-
-    if let Some(current_file) = current_file {
-        if let Some(protocol_arc) = app.load_album_art(&current_file) {
-            if let Ok(mut protocol) = protocol_arc.lock() {
-                // Create a centered area within the inner area
-                let centered_area = center_area(inner_area);
+    if let Some(art) = art {
+        let Some(protocol) = &art.protocol else {
+            // Too large to decode: there is only its size to go on
+            show_album_art_placeholder(
+                f,
+                app,
+                inner_area,
+                Some(&format!(
+                    "Cover too large to show ({}×{})",
+                    art.width, art.height
+                )),
+            );
+            return;
+        };
+        if let Ok(mut protocol) = protocol.lock() {
+            // Create a centered area within the inner area that keeps the cover's proportions
+            let centered_area = center_area(inner_area, (art.width, art.height), font_size);
 
-                let image_widget = StatefulImage::default();
-                f.render_stateful_widget(image_widget, centered_area, &mut *protocol);
+            let image_widget = StatefulImage::default().resize(Resize::Fit(Some(app.art_filter())));
+            f.render_stateful_widget(image_widget, centered_area, &mut *protocol);
 
-                if let Some(Err(e)) = protocol.last_encoding_result() {
-                    let error_msg = Paragraph::new(format!("Render error: {}", e))
-                        .style(Style::default().fg(Color::Red))
-                        .alignment(Alignment::Center);
-                    f.render_widget(error_msg, inner_area);
-                }
+            // The terminal could not take the image after all: show the placeholder instead of
+            // whatever partial output the protocol left behind
+            if let Some(Err(e)) = protocol.last_encoding_result() {
+                f.render_widget(Clear, inner_area);
+                show_album_art_placeholder(
+                    f,
+                    app,
+                    inner_area,
+                    Some(&format!(
+                        "Image display not supported in this terminal ({})",
+                        e
+                    )),
+                );
             }
-        } else {
-            show_album_art_placeholder(f, inner_area);
         }
     } else {
-        show_album_art_placeholder(f, inner_area);
+        show_album_art_placeholder(f, app, inner_area, None);
     }
 }
 
-fn center_area(area: ratatui::layout::Rect) -> ratatui::layout::Rect {
+fn center_area(
+    area: ratatui::layout::Rect,
+    (image_width, image_height): (u32, u32),
+    (font_width, font_height): (u16, u16),
+) -> ratatui::layout::Rect {
     let max_width = area.width;
     let max_height = area.height;
 
-    let target_width = max_width.saturating_sub(2); // Leave some margin
-    let target_height = max_height.saturating_sub(2); // Leave some margin
+    let available_width = max_width.saturating_sub(2); // Leave some margin
+    let available_height = max_height.saturating_sub(2); // Leave some margin
+
+    // Image width expressed in cells per cell of height, accounting for non-square cells
+    let aspect = if image_width == 0 || image_height == 0 || font_width == 0 || font_height == 0 {
+        available_width as f64 / available_height.max(1) as f64
+    } else {
+        (image_width as f64 / font_width as f64) / (image_height as f64 / font_height as f64)
+    };
+
+    let (target_width, target_height) =
+        if (available_width as f64) > available_height as f64 * aspect {
+            // Height is the limiting dimension
+            let width = (available_height as f64 * aspect).round() as u16;
+            (width.clamp(1, available_width.max(1)), available_height)
+        } else {
+            let height = (available_width as f64 / aspect).round() as u16;
+            (available_width, height.clamp(1, available_height.max(1)))
+        };
 
     let x = area.x + (max_width.saturating_sub(target_width)) / 2;
     let y = area.y + (max_height.saturating_sub(target_height)) / 2;
@@ -320,22 +1838,24 @@ fn center_area(area: ratatui::layout::Rect) -> ratatui::layout::Rect {
     }
 }
 
-fn show_album_art_placeholder(f: &mut Frame, area: ratatui::layout::Rect) {
-    let placeholder_content = vec![
-        Line::from(""),
-        Line::from("╭───────────╮"),
-        Line::from("│           │"),
-        Line::from("│    ___    │"),
-        Line::from("│   /   \\   │"),
-        Line::from("│  | 📀 |   │"),
-        Line::from("│   \\___/   │"),
-        Line::from("│           │"),
-        Line::from("╰───────────╯"),
-        Line::from(""),
-    ];
+fn show_album_art_placeholder(
+    f: &mut Frame,
+    app: &App,
+    area: ratatui::layout::Rect,
+    note: Option<&str>,
+) {
+    let mut placeholder_content: Vec<Line> = app
+        .art_placeholder()
+        .iter()
+        .map(|line| Line::from(line.clone()))
+        .collect();
+    if let Some(note) = note {
+        placeholder_content.push(Line::from(note.to_string()));
+    }
 
     let placeholder = Paragraph::new(placeholder_content)
         .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false })
         .style(Style::default().fg(Color::DarkGray));
 
     f.render_widget(placeholder, area);
@@ -0,0 +1,70 @@
+use crate::decode::decode;
+
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Samples per channel summed into one loudness level.
+const WINDOW: usize = 2048;
+
+/// Loudness levels of a file, or why it could not be decoded.
+pub type Levels = Result<Vec<f32>, String>;
+
+/// Computes the loudness levels of `file` on a background thread.
+pub fn spawn(file: String) -> Receiver<Levels> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(levels(&file));
+    });
+    receiver
+}
+
+/// The RMS level of every `WINDOW` samples of the file, mixed down to mono.
+fn levels(path: &str) -> Levels {
+    let mut levels = Vec::new();
+    let mut sum = 0.0f64;
+    let mut count = 0;
+    decode(path, |channels, _, samples| {
+        for frame in samples.chunks(channels.max(1) as usize) {
+            let mono = frame.iter().sum::<f32>() / frame.len() as f32;
+            sum += (mono * mono) as f64;
+            count += 1;
+            if count == WINDOW {
+                levels.push((sum / count as f64).sqrt() as f32);
+                sum = 0.0;
+                count = 0;
+            }
+        }
+        Ok(())
+    })?;
+    if count > 0 {
+        levels.push((sum / count as f64).sqrt() as f32);
+    }
+    if levels.is_empty() {
+        return Err("no audio decoded".to_string());
+    }
+    Ok(levels)
+}
+
+/// Resamples `levels` to `width` bars, each the loudest level it covers, scaled
+/// so the loudest bar of the file is 100.
+pub fn bars(levels: &[f32], width: usize) -> Vec<u64> {
+    if levels.is_empty() || width == 0 {
+        return Vec::new();
+    }
+    let loudest = levels.iter().copied().fold(0.0, f32::max);
+    (0..width)
+        .map(|bar| {
+            let start = bar * levels.len() / width;
+            let end = ((bar + 1) * levels.len() / width).max(start + 1);
+            let level = levels[start..end.min(levels.len())]
+                .iter()
+                .copied()
+                .fold(0.0, f32::max);
+            if loudest > 0.0 {
+                (level / loudest * 100.0).round() as u64
+            } else {
+                0
+            }
+        })
+        .collect()
+}